@@ -22,9 +22,32 @@ select rag.anthropic_messages('2023-06-01', '{
     ]
 }'::json);
 
+-- rag    | blocks_from_pdf                                 | TABLE(page integer, block_index integer, kind text, bbox double precision[], text text) | document bytea | func
+
 -- rag    | chunks_by_character_count                       | text[]           | document text, max_characters integer, max_overlap integer  | func
 select rag.chunks_by_character_count('the cat sat on the mat', 10, 5);
 
+-- rag    | clean_text                                      | text             | text text, options jsonb DEFAULT '{}'::jsonb                | func
+select rag.clean_text(E'A hyphen-\nated line,\nwrapped.', '{"unwrap_lines": false}');
+
+-- rag    | cue_chunks_by_duration                          | TABLE(start_ms bigint, end_ms bigint, text text) | document text, max_duration_ms bigint | func
+select * from rag.cue_chunks_by_duration(E'1\n00:00:01,000 --> 00:00:04,000\nAlice: Hello.\n\n2\n00:00:05,000 --> 00:00:06,000\nBob: Hi.', 10000);
+
+-- rag    | cues_from_subtitles                             | TABLE(start_ms bigint, end_ms bigint, speaker text, text text) | document text | func
+select * from rag.cues_from_subtitles(E'WEBVTT\n\n00:01.000 --> 00:04.000\n<v Alice>Hello.');
+
+-- rag    | detect_language                                 | text             | text text                                                   | func
+select rag.detect_language('Der schnelle braune Fuchs springt über den faulen Hund.');
+
+-- rag    | detect_language_scores                          | jsonb            | text text                                                   | func
+select rag.detect_language_scores('Der schnelle braune Fuchs springt über den faulen Hund.');
+
+-- rag    | documents_from_zip                              | TABLE(path text, mime_type text, text text, error text) | document bytea       | func
+
+-- rag    | email_attachments                               | TABLE(filename text, content_type text, content bytea) | document bytea        | func
+
+-- rag    | emails_from_mbox                                | SETOF bytea      | document bytea                                              | func
+
 -- rag    | fireworks_set_api_key                           | void             | api_key text                                                | func
 select rag.fireworks_set_api_key('abc');
 
@@ -68,9 +91,37 @@ select vector_dims(rag.fireworks_text_embedding_thenlper_gte_large('the cat sat
 select rag.fireworks_text_embedding_whereisai_uae_large_v1('the cat sat on the mat');
 select vector_dims(rag.fireworks_text_embedding_whereisai_uae_large_v1('the cat sat on the mat'));
 
+-- rag    | frontmatter_from_markdown                       | jsonb            | document text                                               | func
+select rag.frontmatter_from_markdown(E'---\ntitle: Notes\n---\n\n# Notes');
+
+-- rag    | images_from_docx                                | TABLE(page_or_index integer, mime_type text, width integer, height integer, alt_text text, data bytea) | document bytea | func
+
+-- rag    | images_from_pdf                                 | TABLE(page_or_index integer, mime_type text, width integer, height integer, alt_text text, data bytea) | document bytea | func
+
+-- rag    | main_content_from_html                          | text             | document text                                               | func
+select rag.main_content_from_html('<nav>Menu</nav><article><p>The article text, which is long enough to count.</p></article>');
+
+-- rag    | markdown_chunks_by_character_count              | text[]           | document text, max_characters integer, max_overlap integer  | func
+select rag.markdown_chunks_by_character_count(E'# Title\n\n* one\n* two\n\n## Next\n\nMore text.', 20, 0);
+
+-- rag    | markdown_from_docx                              | text             | document bytea                                              | func
+
 -- rag    | markdown_from_html                              | text             | document text                                               | func
 select rag.markdown_from_html('<p>Hello</p>');
 
+-- rag    | markdown_from_html                              | text             | document text, options jsonb                                | func
+select rag.markdown_from_html('<nav>Menu</nav><p>Hello</p>', '{"skip_tags": ["nav"]}');
+
+-- rag    | markdown_from_ipynb                             | text             | document jsonb, include_outputs boolean DEFAULT false       | func
+select rag.markdown_from_ipynb('{"cells": [{"cell_type": "code", "source": "1 + 1", "outputs": []}], "metadata": {"kernelspec": {"language": "python"}}}');
+
+-- rag    | markdown_from_xlsx                              | text             | document bytea                                              | func
+
+-- rag    | metadata_from_html                              | jsonb            | document text, base_url text DEFAULT NULL::text             | func
+select rag.metadata_from_html('<title>Hi</title><a href="/next">Next</a>', 'https://example.com/');
+
+-- rag    | metadata_from_pdf                               | jsonb            | document bytea                                              | func
+
 -- rag    | openai_set_api_key                              | void             | api_key text                                                | func
 select rag.openai_set_api_key('qwe');
 
@@ -106,8 +157,20 @@ select vector_dims(rag.openai_text_embedding_3_small('the cat sat on the mat'));
 select rag.openai_text_embedding_ada_002('the cat sat on the mat');
 select vector_dims(rag.openai_text_embedding_ada_002('the cat sat on the mat'));
 
+-- rag    | pages_from_pdf                                  | TABLE(page_number integer, text text) | document bytea                         | func
+-- rag    | parse_email                                     | jsonb            | document bytea                                              | func
+-- rag    | rows_from_xlsx                                  | TABLE(sheet text, row_number integer, "row" jsonb) | document bytea            | func
+-- rag    | text_from_document                              | text             | document bytea, mime_type text DEFAULT NULL::text           | func
 -- rag    | text_from_docx                                  | text             | document bytea                                              | func
+-- rag    | text_from_docx_parts                            | jsonb            | document bytea                                              | func
+-- rag    | text_from_epub                                  | TABLE(chapter integer, text text) | document bytea                             | func
+-- rag    | text_from_json                                  | text             | value jsonb, template text                                  | func
+-- rag    | text_from_markdown                              | text             | document text                                               | func
+-- rag    | text_from_odt                                   | text             | document bytea                                              | func
 -- rag    | text_from_pdf                                   | text             | document bytea                                              | func
+-- rag    | text_from_pdf                                   | text             | document bytea, password text                               | func
+-- rag    | text_from_pptx                                  | TABLE(slide integer, title text, body text, notes text) | document bytea       | func
+-- rag    | text_from_xml                                   | text             | document xml, xpath_map jsonb, template text DEFAULT NULL::text | func
 
 -- rag    | voyageai_set_api_key                            | void             | api_key text                                                | func
 select rag.voyageai_set_api_key('uio');
@@ -4,7 +4,7 @@ use pgrx::prelude::*;
 mod rag {
     use super::super::errors::*;
     use pgrx::prelude::*;
-    use text_splitter::{ChunkConfig, TextSplitter};
+    use text_splitter::{ChunkConfig, MarkdownSplitter, TextSplitter};
 
     #[pg_extern(immutable, strict)]
     pub fn chunks_by_character_count(document: &str, max_characters: i32, max_overlap: i32) -> Vec<&str> {
@@ -19,6 +19,20 @@ mod rag {
         let splitter = TextSplitter::new(config);
         splitter.chunks(document).collect()
     }
+
+    #[pg_extern(immutable, strict)]
+    pub fn markdown_chunks_by_character_count(document: &str, max_characters: i32, max_overlap: i32) -> Vec<&str> {
+        if max_characters < 1 || max_overlap < 0 {
+            error!("{ERR_PREFIX} max_characters must be >= 1 and max_overlap must be >= 0");
+        }
+
+        let config = ChunkConfig::new(max_characters as usize)
+            .with_overlap(max_overlap as usize)
+            .expect_or_pg_err("Error creating chunk config");
+
+        let splitter = MarkdownSplitter::new(config);
+        splitter.chunks(document).collect()
+    }
 }
 
 #[cfg(any(test, feature = "pg_test"))]
@@ -70,4 +84,28 @@ mod tests {
             vec![] as Vec<&str>
         );
     }
+
+    const MARKDOWN: &str = "# Fruit\n\nSome fruit:\n\n* Apples\n* Pears\n\n## Prices\n\n| Fruit | Price |\n|-------|-------|\n| Apple | 1 |\n\n```\nlet x = 1;\n```";
+
+    #[pg_test]
+    fn test_markdown_chunk_by_characters() {
+        assert_eq!(
+            markdown_chunks_by_character_count(MARKDOWN, 50, 0),
+            vec![
+                "# Fruit\n\nSome fruit:\n\n* Apples\n* Pears",
+                "## Prices",
+                "| Fruit | Price |\n|-------|-------|\n| Apple | 1 |",
+                "```\nlet x = 1;\n```"
+            ]
+        );
+        assert_ne!(
+            chunks_by_character_count(MARKDOWN, 50, 0),
+            markdown_chunks_by_character_count(MARKDOWN, 50, 0)
+        );
+    }
+
+    #[pg_test(error = "[rag] max_characters must be >= 1 and max_overlap must be >= 0")]
+    fn test_markdown_chunk_by_characters_invalid() {
+        markdown_chunks_by_character_count(MARKDOWN, 0, 0);
+    }
 }
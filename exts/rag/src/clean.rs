@@ -0,0 +1,293 @@
+use pgrx::prelude::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+const PAGE_BREAK: char = '\x0c'; // form feed
+const MIN_REPEATS: usize = 3;
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CleanOptions {
+    strip_control_characters: bool,
+    // None (the default) removes them only from text with enough pages to tell running headers from content
+    remove_repeated_lines: Option<bool>,
+    dehyphenate: bool,
+    unwrap_lines: bool,
+    collapse_whitespace: bool,
+}
+
+impl Default for CleanOptions {
+    fn default() -> Self {
+        CleanOptions {
+            strip_control_characters: true,
+            remove_repeated_lines: None,
+            dehyphenate: true,
+            unwrap_lines: true,
+            collapse_whitespace: true,
+        }
+    }
+}
+
+// Lines that differ only in their numbers (e.g. "Page 3 of 12") or spacing are counted as repeats
+fn line_key(line: &str) -> Option<String> {
+    let mut key = String::new();
+    for word in line.split_whitespace() {
+        if !key.is_empty() {
+            key.push(' ');
+        }
+        for c in word.chars() {
+            if !c.is_ascii_digit() {
+                key.push(c);
+            } else if !key.ends_with('#') {
+                key.push('#');
+            }
+        }
+    }
+    (!key.is_empty()).then_some(key)
+}
+
+// Removes running headers and footers. Given at least 3 pages separated by form feeds, a line is removed if it's on at
+// least 3 of them. Otherwise, if asked to, it removes lines that occur at least 3 times. Without pages to go on, the
+// line must be repeated exactly: numbers may matter, as in "Step 1".
+fn remove_repeated_lines(text: &str, without_pages: bool) -> String {
+    let pages: Vec<&str> = text.split(PAGE_BREAK).collect();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let paged = pages.len() >= MIN_REPEATS;
+    let key = |line: &str| match paged {
+        true => line_key(line),
+        false => (!line.trim().is_empty()).then(|| line.trim().to_string()),
+    };
+    if paged {
+        for page in &pages {
+            for key in page.split('\n').filter_map(key).collect::<HashSet<_>>() {
+                *counts.entry(key).or_default() += 1;
+            }
+        }
+    } else if without_pages {
+        for key in text.split('\n').filter_map(key) {
+            *counts.entry(key).or_default() += 1;
+        }
+    } else {
+        return text.to_string();
+    }
+    let is_repeated = |line: &str| key(line).is_some_and(|key| counts[&key] >= MIN_REPEATS);
+    pages
+        .iter()
+        .map(|page| {
+            page.split('\n')
+                .filter(|line| !is_repeated(line))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join(&PAGE_BREAK.to_string())
+}
+
+// Rejoins words hyphenated across a line break, when the word continues in lower case (e.g. "exam-\nple", but not
+// "Jean-\nPaul"). Soft hyphens at the end of a line are treated the same way.
+fn dehyphenate(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let skip_blanks = |mut i: usize| {
+        while i < chars.len() && matches!(chars[i], ' ' | '\t') {
+            i += 1;
+        }
+        i
+    };
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if matches!(chars[i], '-' | '\u{ad}') && i > 0 && chars[i - 1].is_alphabetic() {
+            let newline = skip_blanks(i + 1);
+            if newline < chars.len() && chars[newline] == '\n' {
+                let next = skip_blanks(newline + 1);
+                if next < chars.len() && chars[next].is_lowercase() {
+                    i = next;
+                    continue;
+                }
+            }
+        }
+        result.push(chars[i]);
+        i += 1;
+    }
+    result
+}
+
+// Keeps line breaks and tabs, and turns page breaks into paragraph breaks. Also removes some invisible characters
+// that often turn up in extracted text: soft hyphens, zero-width spaces and byte order marks.
+fn strip_control_characters(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\n' | '\t' => result.push(c),
+            PAGE_BREAK => result.push_str("\n\n"),
+            '\u{ad}' | '\u{200b}' | '\u{feff}' => {}
+            c if c.is_control() => {}
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn ends_sentence(line: &str) -> bool {
+    line.trim_end()
+        .trim_end_matches(['"', '\'', ')', ']', '”', '’'])
+        .ends_with(['.', '!', '?', ':'])
+}
+
+fn starts_list_item(line: &str) -> bool {
+    let line = line.trim_start();
+    if ["- ", "* ", "• ", "– "].iter().any(|bullet| line.starts_with(bullet)) {
+        return true;
+    }
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+    rest.len() < line.len() && (rest.starts_with(". ") || rest.starts_with(") "))
+}
+
+// Joins each line of a paragraph to the next, unless it ends a sentence or the next line starts a list item. Lines
+// ending in a hyphen are joined without a space. Paragraphs are separated by blank lines, which are kept.
+fn unwrap_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut result = String::with_capacity(text.len());
+    let mut joined = false;
+    for (i, line) in lines.iter().enumerate() {
+        let line = if joined { line.trim_start() } else { line };
+        joined = lines.get(i + 1).is_some_and(|next| {
+            !line.trim().is_empty() && !next.trim().is_empty() && !ends_sentence(line) && !starts_list_item(next)
+        });
+        if joined {
+            let line = line.trim_end();
+            result.push_str(line);
+            if !line.ends_with('-') {
+                result.push(' ');
+            }
+        } else {
+            result.push_str(line);
+            if i + 1 < lines.len() {
+                result.push('\n');
+            }
+        }
+    }
+    result
+}
+
+// Collapses runs of spaces within lines (keeping page breaks), trims lines, and leaves no more than one blank line
+// between paragraphs.
+fn collapse_whitespace(text: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    for line in text.split('\n') {
+        let line = line
+            .split(|c: char| c.is_whitespace() && c != PAGE_BREAK)
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !line.is_empty() || lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n").trim_end().to_string()
+}
+
+fn clean(text: &str, options: CleanOptions) -> String {
+    let mut text = text.replace("\r\n", "\n").replace('\r', "\n");
+    if options.remove_repeated_lines != Some(false) {
+        text = remove_repeated_lines(&text, options.remove_repeated_lines == Some(true));
+    }
+    if options.dehyphenate {
+        text = dehyphenate(&text);
+    }
+    if options.strip_control_characters {
+        text = strip_control_characters(&text);
+    }
+    if options.unwrap_lines {
+        text = unwrap_lines(&text);
+    }
+    if options.collapse_whitespace {
+        text = collapse_whitespace(&text);
+    }
+    text
+}
+
+#[pg_schema]
+mod rag {
+    use super::super::errors::*;
+    use super::clean;
+    use pgrx::prelude::*;
+
+    #[pg_extern(immutable, strict)]
+    pub fn clean_text(text: &str, options: default!(pgrx::JsonB, "'{}'")) -> String {
+        let options = serde_json::from_value(options.0).expect_or_pg_err("Invalid clean_text options");
+        clean(text, options)
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+    use serde_json::json;
+
+    const PAGES: &str =
+        "ACME Annual Report\n\nThe results this year were ex-\ncellent, thanks\r\nto our  customers.\n\nPage 1 of 3\
+        \x0cACME Annual Report\n\nRevenue grew\u{200b} in\tevery region:\n- North\n- South\n\n\nPage 2 of 3\
+        \x0cACME Annual Report\n\nWe thank Jean-\nPaul for his\u{7} work.\nPage 3 of 3";
+
+    #[pg_test]
+    fn test_clean_text() {
+        assert_eq!(
+            clean_text(PAGES, pgrx::JsonB(json!({}))),
+            "The results this year were excellent, thanks to our customers.\n\n\
+            Revenue grew in every region:\n- North\n- South\n\n\
+            We thank Jean-Paul for his work."
+        )
+    }
+
+    #[pg_test]
+    fn test_clean_text_selected_steps() {
+        let options = json!({"remove_repeated_lines": false, "unwrap_lines": false, "collapse_whitespace": false});
+        assert_eq!(
+            clean_text("Title\n\nA hyphen-\n  ated\u{0}  word\n", pgrx::JsonB(options)),
+            "Title\n\nA hyphenated  word\n"
+        )
+    }
+
+    #[pg_test]
+    fn test_clean_text_no_steps() {
+        let options = json!({
+            "strip_control_characters": false,
+            "remove_repeated_lines": false,
+            "dehyphenate": false,
+            "unwrap_lines": false,
+            "collapse_whitespace": false,
+        });
+        assert_eq!(clean_text(PAGES, pgrx::JsonB(options)), PAGES.replace("\r\n", "\n"))
+    }
+
+    #[pg_test]
+    fn test_clean_text_repeated_lines_without_pages() {
+        let text = "Header\nStep 1\nOne.\n\nHeader\nStep 2\nTwo.\n\nHeader\nStep 3\nThree.";
+        assert_eq!(
+            clean_text(
+                text,
+                pgrx::JsonB(json!({"unwrap_lines": false, "remove_repeated_lines": true}))
+            ),
+            "Step 1\nOne.\n\nStep 2\nTwo.\n\nStep 3\nThree."
+        );
+        // by default, there's no telling a header from a line that's legitimately repeated
+        assert_eq!(clean_text(text, pgrx::JsonB(json!({"unwrap_lines": false}))), text);
+    }
+
+    #[pg_test]
+    fn test_clean_text_repeated_lines_on_few_pages() {
+        let text = "Q: Is it on?\nA: Yes.\n\x0cQ: Is it plugged in?\nA: Yes.";
+        assert_eq!(
+            clean_text(text, pgrx::JsonB(json!({"unwrap_lines": false}))),
+            "Q: Is it on?\nA: Yes.\n\nQ: Is it plugged in?\nA: Yes."
+        );
+    }
+
+    #[pg_test(error = "[rag] Invalid clean_text options: invalid type: string \"yes\", expected a boolean")]
+    fn test_clean_text_invalid_option() {
+        clean_text("text", pgrx::JsonB(json!({"dehyphenate": "yes"})));
+    }
+}
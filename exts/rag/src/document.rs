@@ -0,0 +1,479 @@
+use crate::errors::*;
+use crate::limits::{
+    check_document_size, check_zip_entries, decompressed_zip_size, limit_exceeded, max_bytes, session_limits,
+};
+use crate::{docx, email, epub, markdown, odt, pdf, pptx, xlsx};
+use pgrx::pg_sys::panic::CaughtError;
+use pgrx::prelude::*;
+use std::collections::HashSet;
+use std::io::{Cursor, Read};
+
+pub const MIME_PDF: &str = "application/pdf";
+pub const MIME_DOCX: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+pub const MIME_PPTX: &str = "application/vnd.openxmlformats-officedocument.presentationml.presentation";
+pub const MIME_XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+pub const MIME_XLSB: &str = "application/vnd.ms-excel.sheet.binary.macroenabled.12";
+pub const MIME_XLS: &str = "application/vnd.ms-excel";
+pub const MIME_CSV: &str = "text/csv";
+pub const MIME_ODT: &str = "application/vnd.oasis.opendocument.text";
+pub const MIME_ODS: &str = "application/vnd.oasis.opendocument.spreadsheet";
+pub const MIME_EPUB: &str = "application/epub+zip";
+pub const MIME_EMAIL: &str = "message/rfc822";
+pub const MIME_HTML: &str = "text/html";
+pub const MIME_TEXT: &str = "text/plain";
+pub const MIME_ZIP: &str = "application/zip";
+pub const MIME_OLE: &str = "application/x-ole-storage";
+
+// a row of images_from_pdf or images_from_docx: page or index, MIME type, width, height, alt text and data
+pub type ImageRow = (i32, String, Option<i32>, Option<i32>, Option<String>, Vec<u8>);
+
+// headers that commonly appear at the start of an email message, lower-cased
+const EMAIL_HEADERS: [&str; 11] = [
+    "from",
+    "to",
+    "cc",
+    "return-path",
+    "received",
+    "delivered-to",
+    "message-id",
+    "mime-version",
+    "content-type",
+    "date",
+    "subject",
+];
+
+// the most lines of a header block that are looked at when sniffing for email
+const MAX_SNIFFED_HEADER_LINES: usize = 64;
+
+const MAX_DECLARED_MIME_TYPE_BYTES: u64 = 256;
+
+// zip-based formats are told apart by the files they contain
+fn sniff_zip(document: &[u8]) -> &'static str {
+    let Ok(mut zip) = zip::ZipArchive::new(Cursor::new(document)) else {
+        return MIME_ZIP;
+    };
+    // ODF and EPUB files start with an uncompressed file named mimetype, which says what they are (and is short)
+    let mut declared = String::new();
+    if let Ok(file) = zip.by_name("mimetype") {
+        let _ = file.take(MAX_DECLARED_MIME_TYPE_BYTES).read_to_string(&mut declared);
+    }
+    let declared = declared.trim();
+    for mime_type in [MIME_EPUB, MIME_ODT, MIME_ODS] {
+        if declared == mime_type {
+            return mime_type;
+        }
+    }
+    let names: Vec<&str> = zip.file_names().collect();
+    for (file, mime_type) in [
+        ("word/document.xml", MIME_DOCX),
+        ("ppt/presentation.xml", MIME_PPTX),
+        ("xl/workbook.xml", MIME_XLSX),
+        ("xl/workbook.bin", MIME_XLSB),
+    ] {
+        if names.contains(&file) {
+            return mime_type;
+        }
+    }
+    MIME_ZIP
+}
+
+// Legacy Office formats share a container, whose directory says what's in it: of these formats, only Excel workbooks
+// are supported. Only the directory is read, not the streams themselves.
+fn sniff_ole(document: &[u8]) -> &'static str {
+    let Ok(container) = cfb::CompoundFile::open(Cursor::new(document)) else {
+        return MIME_OLE;
+    };
+    // Excel 5 and later call the workbook stream Workbook, and earlier versions call it Book
+    match container.is_stream("/Workbook") || container.is_stream("/Book") {
+        true => MIME_XLS,
+        false => MIME_OLE,
+    }
+}
+
+// Text is taken for an email if it starts with a block of header lines (after any mbox "From " separator line), up to
+// a blank line, that includes at least two distinct headers common in email. A single line such as "Subject: notes"
+// isn't enough.
+fn looks_like_email(text: &str) -> bool {
+    let mut lines = text.lines().peekable();
+    if lines.peek().is_some_and(|line| line.starts_with("From ")) {
+        lines.next();
+    }
+    let mut headers = HashSet::new();
+    for (i, line) in lines.take(MAX_SNIFFED_HEADER_LINES).enumerate() {
+        if line.trim().is_empty() {
+            break;
+        }
+        // a header's value can continue on lines that start with whitespace
+        if i > 0 && line.starts_with([' ', '\t']) {
+            continue;
+        }
+        let Some((name, _)) = line.split_once(':') else {
+            return false;
+        };
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic()) {
+            return false;
+        }
+        let name = name.to_ascii_lowercase();
+        if EMAIL_HEADERS.contains(&name.as_str()) {
+            headers.insert(name);
+        }
+    }
+    headers.len() >= 2
+}
+
+// returns None if the document's type can't be identified
+pub fn sniff_mime_type(document: &[u8]) -> Option<&'static str> {
+    if document.starts_with(b"%PDF-") {
+        return Some(MIME_PDF);
+    }
+    if document.starts_with(b"PK\x03\x04") {
+        return Some(sniff_zip(document));
+    }
+    if document.starts_with(b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1") {
+        return Some(sniff_ole(document));
+    }
+
+    let text = std::str::from_utf8(document).ok()?;
+    let start = text.trim_start_matches('\u{FEFF}').trim_start();
+    let prefix = start.get(..64).unwrap_or(start).to_lowercase();
+    if prefix.starts_with("<!doctype html") || prefix.starts_with("<html") {
+        return Some(MIME_HTML);
+    }
+    if looks_like_email(start) {
+        return Some(MIME_EMAIL);
+    }
+    // control characters other than whitespace suggest a binary format
+    if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return None;
+    }
+    Some(MIME_TEXT)
+}
+
+// maps alternative names onto those used above, and drops any parameters (such as charset)
+fn canonical_mime_type(mime_type: &str) -> String {
+    let mime_type = mime_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    match mime_type.as_str() {
+        "application/xhtml+xml" => MIME_HTML.to_string(),
+        "text/markdown" | "text/x-markdown" => MIME_TEXT.to_string(),
+        "application/x-pdf" => MIME_PDF.to_string(),
+        "text/tab-separated-values" | "application/csv" => MIME_CSV.to_string(),
+        _ => mime_type,
+    }
+}
+
+pub fn text_from_document_of_type(document: &[u8], mime_type: &str) -> String {
+    check_document_size(document);
+    let mime_type = canonical_mime_type(mime_type);
+    match mime_type.as_str() {
+        MIME_PDF => pdf::rag::text_from_pdf(document),
+        MIME_DOCX => docx::rag::text_from_docx(document.to_vec()),
+        MIME_PPTX => pptx::rag::text_from_pptx(document.to_vec())
+            .map(|(_, title, body, notes)| {
+                [title, Some(body), notes]
+                    .into_iter()
+                    .flatten()
+                    .filter(|text| !text.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        MIME_XLSX | MIME_XLSB | MIME_XLS | MIME_ODS | MIME_CSV => xlsx::rag::markdown_from_xlsx(document.to_vec()),
+        MIME_ODT => odt::rag::text_from_odt(document.to_vec()),
+        MIME_EPUB => epub::rag::text_from_epub(document.to_vec())
+            .map(|(_, text)| text)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        MIME_EMAIL => {
+            let email = email::rag::parse_email(document).0;
+            email["body"].as_str().unwrap_or_default().to_string()
+        }
+        MIME_HTML => markdown::html_to_markdown(&String::from_utf8_lossy(document)),
+        MIME_TEXT => String::from_utf8_lossy(document).into_owned(),
+        other => error!("{ERR_PREFIX} Unsupported document format: {other}"),
+    }
+}
+
+#[pg_schema]
+mod rag {
+    use super::super::worker;
+    use pgrx::prelude::*;
+
+    // not strict, so that the MIME type can be NULL: a NULL document gives NULL, as it would if it were strict
+    #[pg_extern(immutable)]
+    pub fn text_from_document(document: Option<&[u8]>, mime_type: default!(Option<&str>, "NULL")) -> Option<String> {
+        let arguments: Vec<&str> = mime_type.into_iter().collect();
+        Some(worker::extract_value(
+            "text_from_document",
+            document?.to_vec(),
+            &arguments,
+        ))
+    }
+
+    #[allow(clippy::type_complexity)]
+    #[pg_extern(immutable, strict)]
+    pub fn documents_from_zip(
+        document: &[u8],
+    ) -> TableIterator<
+        'static,
+        (
+            name!(path, String),
+            name!(mime_type, Option<String>),
+            name!(text, Option<String>),
+            name!(error, Option<String>),
+        ),
+    > {
+        TableIterator::new(worker::extract("documents_from_zip", document.to_vec(), &[]))
+    }
+}
+
+// text_from_document: the type is identified from the document itself if it isn't given
+pub fn document_text(document: &[u8], mime_type: Option<&str>) -> String {
+    let mime_type = match mime_type {
+        Some(mime_type) => mime_type,
+        None => sniff_mime_type(document).unwrap_or_pg_err("Unsupported document format: couldn't identify type"),
+    };
+    text_from_document_of_type(document, mime_type)
+}
+
+type ZipDocument = (String, Option<String>, Option<String>, Option<String>);
+
+pub fn zip_documents(document: &[u8]) -> Vec<ZipDocument> {
+    let limits = session_limits();
+    documents_from_zip_with_limits(document, limits.max_zip_entries, limits.max_decompressed_bytes)
+}
+
+fn zip_contents_too_large(max_decompressed_bytes: i32) -> ! {
+    limit_exceeded(&format!(
+        ".zip file contents are too large when decompressed (rag.max_decompressed_bytes is {max_decompressed_bytes})"
+    ));
+}
+
+// Errors in extracting text from individual entries are returned rather than raised, so one bad file doesn't spoil the
+// rest, but exceeding the limits (which guard against zip bombs) is an error. The decompressed size limit covers the
+// contents of zip-based documents (such as .docx files) inside the .zip too, since their extractors decompress them.
+fn documents_from_zip_with_limits(document: &[u8], max_entries: i32, max_decompressed_bytes: i32) -> Vec<ZipDocument> {
+    check_document_size(document);
+    let mut zip = zip::ZipArchive::new(Cursor::new(document)).expect_or_pg_err("Couldn't read .zip file");
+    check_zip_entries(zip.len(), max_entries);
+
+    let mut remaining_bytes = max_bytes(max_decompressed_bytes);
+    let mut documents = Vec::new();
+    for index in 0..zip.len() {
+        let path = zip.name_for_index(index).unwrap_or_default().to_string();
+        // skip directories, and the resource forks that macOS adds to archives it creates
+        if path.ends_with('/') || path.starts_with("__MACOSX/") {
+            continue;
+        }
+        let mut contents = Vec::new();
+        let read = zip.by_index(index).and_then(|entry| {
+            // an entry's declared size can't be trusted, so read no more than one byte beyond what's allowed
+            entry
+                .take(remaining_bytes.saturating_add(1))
+                .read_to_end(&mut contents)?;
+            Ok(())
+        });
+        if let Err(err) = read {
+            documents.push((
+                path,
+                None,
+                None,
+                Some(format!("{ERR_PREFIX} Couldn't read file from .zip: {err}")),
+            ));
+            continue;
+        }
+        if contents.len() as u64 > remaining_bytes {
+            zip_contents_too_large(max_decompressed_bytes);
+        }
+        remaining_bytes -= contents.len() as u64;
+        if let Ok(mut inner) = zip::ZipArchive::new(Cursor::new(&contents)) {
+            check_zip_entries(inner.len(), max_entries);
+            match decompressed_zip_size(&mut inner, remaining_bytes) {
+                Some(inner_bytes) => remaining_bytes -= inner_bytes,
+                None => zip_contents_too_large(max_decompressed_bytes),
+            }
+        }
+
+        let Some(mime_type) = sniff_mime_type(&contents) else {
+            let error = format!("{ERR_PREFIX} Unsupported document format: couldn't identify type");
+            documents.push((path, None, None, Some(error)));
+            continue;
+        };
+        // Only errors raised by our own (Rust) code are caught: it doesn't touch Postgres's state, so there's nothing to
+        // roll back. Errors raised by Postgres itself, such as query cancellation, would need a subtransaction.
+        let result = PgTryBuilder::new(|| Ok(text_from_document_of_type(&contents, mime_type)))
+            .catch_others(|error| match error {
+                CaughtError::ErrorReport(report) | CaughtError::RustPanic { ereport: report, .. } => {
+                    Err(report.message().to_string())
+                }
+                CaughtError::PostgresError(_) => error.rethrow(),
+            })
+            .execute();
+        let (text, error) = match result {
+            Ok(text) => (Some(text), None),
+            Err(error) => (None, Some(error)),
+        };
+        documents.push((path, Some(mime_type.to_string()), text, error));
+    }
+    documents
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use super::*;
+
+    #[pg_test]
+    fn test_sniff_mime_type() {
+        assert_eq!(sniff_mime_type(include_bytes!("../test_res/test.pdf")), Some(MIME_PDF));
+        assert_eq!(
+            sniff_mime_type(include_bytes!("../test_res/test.docx")),
+            Some(MIME_DOCX)
+        );
+        assert_eq!(
+            sniff_mime_type(include_bytes!("../test_res/test.pptx")),
+            Some(MIME_PPTX)
+        );
+        assert_eq!(
+            sniff_mime_type(include_bytes!("../test_res/test.xlsx")),
+            Some(MIME_XLSX)
+        );
+        assert_eq!(sniff_mime_type(include_bytes!("../test_res/test.ods")), Some(MIME_ODS));
+        assert_eq!(sniff_mime_type(include_bytes!("../test_res/test.odt")), Some(MIME_ODT));
+        assert_eq!(
+            sniff_mime_type(include_bytes!("../test_res/test.epub")),
+            Some(MIME_EPUB)
+        );
+        assert_eq!(
+            sniff_mime_type(include_bytes!("../test_res/test.eml")),
+            Some(MIME_EMAIL)
+        );
+        assert_eq!(
+            sniff_mime_type(include_bytes!("../test_res/test.pages")),
+            Some(MIME_ZIP)
+        );
+        assert_eq!(sniff_mime_type(b"\n<!DOCTYPE html><p>Hi</p>"), Some(MIME_HTML));
+        assert_eq!(sniff_mime_type(b"Just some text"), Some(MIME_TEXT));
+        assert_eq!(
+            sniff_mime_type(
+                b"From ada@example.com Thu Aug 22 20:05:55 2024\nFrom: ada@example.com\nSubject: Hi\n\nHello"
+            ),
+            Some(MIME_EMAIL)
+        );
+        assert_eq!(sniff_mime_type(b"Subject: notes\n\nbody"), Some(MIME_TEXT));
+        assert_eq!(
+            sniff_mime_type(b"Date: Monday\nSubject: notes\nThe engine works.\n"),
+            Some(MIME_TEXT)
+        );
+        assert_eq!(sniff_mime_type(b"Date,Amount\n2024-08-22,12.50\n"), Some(MIME_TEXT));
+        assert_eq!(
+            sniff_mime_type(b"From here on, it's notes\nDate: Monday\n"),
+            Some(MIME_TEXT)
+        );
+        assert_eq!(sniff_mime_type(b"\x00\x01\x02\x03"), None);
+    }
+
+    // a compound file (the container of legacy Office formats) holding an empty stream of the given name
+    fn ole_with_stream(name: &str) -> Vec<u8> {
+        let mut container = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        container.create_stream(name).unwrap();
+        container.into_inner().into_inner()
+    }
+
+    #[pg_test]
+    fn test_sniff_ole_mime_type() {
+        assert_eq!(sniff_mime_type(&ole_with_stream("/Workbook")), Some(MIME_XLS));
+        assert_eq!(sniff_mime_type(&ole_with_stream("/Book")), Some(MIME_XLS));
+        assert_eq!(sniff_mime_type(&ole_with_stream("/WordDocument")), Some(MIME_OLE));
+        assert_eq!(
+            sniff_mime_type(b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1 but truncated"),
+            Some(MIME_OLE)
+        );
+    }
+
+    #[pg_test]
+    fn test_text_from_document() {
+        assert_eq!(
+            text_from_document(Some(include_bytes!("../test_res/test.odt")), None),
+            Some(odt::rag::text_from_odt(include_bytes!("../test_res/test.odt").to_vec()))
+        );
+        assert_eq!(
+            text_from_document(Some(include_bytes!("../test_res/test.pptx")), None).as_deref(),
+            Some("Quarterly review\n\nPrepared by the team\n\nResults\n\nRevenue up\nCosts down\nGrouped note\nQ1\n10\nQ2\n12\n\nMention the new pricing\n\nNo title here")
+        );
+        assert_eq!(
+            text_from_document(Some(b"<h1>Heading</h1>"), Some("text/html; charset=utf-8")).as_deref(),
+            Some("# Heading")
+        );
+        assert_eq!(
+            text_from_document(Some(b"a\tb\n1\t2"), Some("text/tab-separated-values")).as_deref(),
+            Some("# Sheet1\n\n| a | b |\n| --- | --- |\n| 1 | 2 |")
+        );
+        assert_eq!(
+            text_from_document(Some(b"Subject: notes\n\nbody"), None).as_deref(),
+            Some("Subject: notes\n\nbody")
+        );
+        assert_eq!(text_from_document(None, Some("application/pdf")), None);
+    }
+
+    #[pg_test]
+    fn test_documents_from_zip() {
+        let documents: Vec<_> = documents_from_zip(include_bytes!("../test_res/test.zip")).collect();
+        let odt_text = odt::rag::text_from_odt(include_bytes!("../test_res/test.odt").to_vec());
+        let some = |text: &str| Some(text.to_string());
+        assert_eq!(
+            documents,
+            vec![
+                ("docs/test.odt".to_string(), some(MIME_ODT), Some(odt_text), None),
+                (
+                    "notes.txt".to_string(),
+                    some(MIME_TEXT),
+                    some("Plain text notes\n"),
+                    None
+                ),
+                ("page.html".to_string(), some(MIME_HTML), some("# Page"), None),
+                (
+                    "data.bin".to_string(),
+                    None,
+                    None,
+                    some("[rag] Unsupported document format: couldn't identify type")
+                ),
+                (
+                    "broken.epub".to_string(),
+                    some(MIME_EPUB),
+                    None,
+                    some("[rag] Couldn't find container in .epub file")
+                ),
+            ]
+        );
+    }
+
+    #[pg_test(error = "[rag] .zip file has too many entries (7, but rag.max_zip_entries is 6)")]
+    fn test_documents_from_zip_with_too_many_entries() {
+        documents_from_zip_with_limits(include_bytes!("../test_res/test.zip"), 6, 1024 * 1024);
+    }
+
+    #[pg_test(error = "[rag] .zip file contents are too large when decompressed (rag.max_decompressed_bytes is 1000)")]
+    fn test_documents_from_zip_with_too_many_bytes() {
+        documents_from_zip_with_limits(include_bytes!("../test_res/test.zip"), 100, 1000);
+    }
+
+    // test.zip's entries come to 1,260 bytes decompressed, but test.odt, one of those entries, comes to 1,749 bytes more
+    #[pg_test(error = "[rag] .zip file contents are too large when decompressed (rag.max_decompressed_bytes is 2000)")]
+    fn test_documents_from_zip_with_too_many_nested_bytes() {
+        documents_from_zip_with_limits(include_bytes!("../test_res/test.zip"), 100, 2000);
+    }
+
+    #[pg_test(error = "[rag] Unsupported document format: application/zip")]
+    fn test_text_from_unsupported_document() {
+        text_from_document(Some(include_bytes!("../test_res/test.pages")), None);
+    }
+
+    #[pg_test(error = "[rag] Unsupported document format: image/png")]
+    fn test_text_from_document_of_unsupported_type() {
+        text_from_document(Some(b"\x89PNG"), Some("image/png"));
+    }
+}
@@ -1,15 +1,27 @@
 use pgrx::prelude::*;
 
 #[pg_schema]
-mod rag {
+pub(crate) mod rag {
+    use super::super::document::ImageRow;
     use super::super::errors::*;
+    use super::super::limits::{check_document_size, check_zip_document};
+    use super::super::package::Package;
+    use super::super::worker;
+    use docx_rust::document::{
+        BodyContent, Paragraph, ParagraphContent, Run, RunContent, Table, TableCellContent, TableRowContent,
+    };
+    use docx_rust::formatting::CharacterProperty;
+    use docx_rust::{Docx, DocxFile};
     use pgrx::prelude::*;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
     use std::io::Cursor;
 
-    #[pg_extern(immutable, strict)]
-    pub fn text_from_docx(document: Vec<u8>) -> String {
+    fn with_docx<T>(document: Vec<u8>, f: impl FnOnce(&Docx) -> T) -> T {
+        check_document_size(&document);
+        check_zip_document(&document);
         // DocxError doesn't implement Display, so we can't use .expect_or_pg_err()
-        let file = match docx_rust::DocxFile::from_reader(Cursor::new(document)) {
+        let file = match DocxFile::from_reader(Cursor::new(document)) {
             Err(err) => error!("{ERR_PREFIX} Couldn't read .docx file: {:?}", err),
             Ok(value) => value,
         };
@@ -17,7 +29,559 @@ mod rag {
             Err(err) => error!("{ERR_PREFIX} Couldn't parse .docx file: {:?}", err),
             Ok(value) => value,
         };
-        docx.document.body.text()
+        f(&docx)
+    }
+
+    pub(crate) fn docx_text(document: Vec<u8>) -> String {
+        with_docx(document, |docx| docx.document.body.text())
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn text_from_docx(document: Vec<u8>) -> String {
+        worker::extract_value("text_from_docx", document, &[])
+    }
+
+    // headers, footers, notes and comments
+
+    // like Body::text(), but leaving out field codes (such as the PAGE instruction often found in footers)
+    fn run_text(run: &Run) -> String {
+        run.content
+            .iter()
+            .filter_map(|content| match content {
+                RunContent::Text(text) => Some(text.text.as_ref()),
+                RunContent::Tab(_) | RunContent::PTab(_) => Some("\t"),
+                RunContent::Break(_) | RunContent::CarriageReturn(_) => Some("\n"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn paragraph_text(paragraph: &Paragraph) -> String {
+        paragraph
+            .content
+            .iter()
+            .map(|content| match content {
+                ParagraphContent::Run(run) => run_text(run),
+                ParagraphContent::Link(link) => link.content.iter().map(run_text).collect(),
+                ParagraphContent::SDT(sdt) => sdt
+                    .content
+                    .iter()
+                    .map(|content| text_from_body_contents(&content.content))
+                    .collect(),
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    fn text_from_body_contents(contents: &[BodyContent]) -> String {
+        let texts: Vec<String> = contents
+            .iter()
+            .filter_map(|content| match content {
+                BodyContent::Paragraph(para) => Some(paragraph_text(para)),
+                BodyContent::Run(run) => Some(run_text(run)),
+                BodyContent::Sdt(sdt) => sdt
+                    .content
+                    .as_ref()
+                    .map(|content| text_from_body_contents(&content.content)),
+                _ => None,
+            })
+            .collect();
+        texts.join("\r\n").trim().to_string()
+    }
+
+    fn non_empty_texts<'c>(contents: impl Iterator<Item = &'c [BodyContent<'c>]>) -> Vec<String> {
+        contents
+            .map(text_from_body_contents)
+            .filter(|text| !text.is_empty())
+            .collect()
+    }
+
+    // headers and footers are keyed by file name (word/header1.xml etc.): this orders them by number, so that
+    // header2.xml comes before header10.xml
+    pub(super) fn part_order(name: &str) -> (&str, u64) {
+        let stem = name.strip_suffix(".xml").unwrap_or(name);
+        let prefix = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+        (prefix, stem[prefix.len()..].parse().unwrap_or(u64::MAX))
+    }
+
+    pub(crate) fn docx_parts(document: Vec<u8>) -> pgrx::JsonB {
+        with_docx(document, |docx| {
+            let mut headers: Vec<_> = docx.headers.iter().collect();
+            headers.sort_by_key(|(name, _)| part_order(name));
+            let mut footers: Vec<_> = docx.footers.iter().collect();
+            footers.sort_by_key(|(name, _)| part_order(name));
+
+            // notes with a type are separators, not real notes
+            let footnotes = docx
+                .footnotes
+                .iter()
+                .flat_map(|notes| &notes.content)
+                .filter(|note| note.ty.is_none());
+            let endnotes = docx
+                .endnotes
+                .iter()
+                .flat_map(|notes| &notes.content)
+                .filter(|note| note.ty.is_none());
+
+            let comments: Vec<Value> = docx
+                .comments
+                .iter()
+                .flat_map(|comments| &comments.comments)
+                .map(|comment| json!({ "author": comment.author, "text": paragraph_text(&comment.content) }))
+                .collect();
+
+            pgrx::JsonB(json!({
+                "body": docx.document.body.text(),
+                "headers": non_empty_texts(headers.into_iter().map(|(_, header)| header.content.as_slice())),
+                "footers": non_empty_texts(footers.into_iter().map(|(_, footer)| footer.content.as_slice())),
+                "footnotes": non_empty_texts(footnotes.map(|note| note.content.as_slice())),
+                "endnotes": non_empty_texts(endnotes.map(|note| note.content.as_slice())),
+                "comments": comments,
+            }))
+        })
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn text_from_docx_parts(document: Vec<u8>) -> pgrx::JsonB {
+        worker::extract_value("text_from_docx_parts", document, &[])
+    }
+
+    // Markdown
+
+    #[derive(Clone, Copy, PartialEq, Default)]
+    struct Emphasis {
+        bold: bool,
+        italic: bool,
+        strike: bool,
+    }
+
+    struct MarkdownWriter<'a, 'b> {
+        docx: &'b Docx<'a>,
+        style_names: HashMap<&'b str, String>,
+        list_counters: HashMap<(isize, isize), usize>,
+        blocks: Vec<String>,
+        list_id: Option<isize>,
+    }
+
+    fn escape_markdown(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    // emphasis markers must hug the text they surround, so any surrounding whitespace is moved outside them
+    fn emphasise(text: &str, emphasis: Emphasis) -> String {
+        let trimmed = text.trim();
+        if trimmed.is_empty() || emphasis == Emphasis::default() {
+            return text.to_string();
+        }
+        let mut marker = String::new();
+        if emphasis.strike {
+            marker.push_str("~~");
+        }
+        if emphasis.bold {
+            marker.push_str("**");
+        }
+        if emphasis.italic {
+            marker.push('_');
+        }
+        let closing: String = marker.chars().rev().collect();
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let trailing = &text[text.trim_end().len()..];
+        format!("{leading}{marker}{trimmed}{closing}{trailing}")
+    }
+
+    impl<'a, 'b> MarkdownWriter<'a, 'b> {
+        fn new(docx: &'b Docx<'a>) -> Self {
+            let style_names = docx
+                .styles
+                .styles
+                .iter()
+                .filter_map(|style| {
+                    let name = style.name.as_ref()?.value.to_lowercase();
+                    Some((style.style_id.as_ref(), name))
+                })
+                .collect();
+            MarkdownWriter {
+                docx,
+                style_names,
+                list_counters: HashMap::new(),
+                blocks: vec![],
+                list_id: None,
+            }
+        }
+
+        // list items continue the current list if they share its numbering or are nested within it
+        fn push_block(&mut self, block: String, list_item: Option<(isize, isize)>) {
+            if let (Some(list_id), Some((num_id, level))) = (self.list_id, list_item) {
+                if let Some(last) = self.blocks.last_mut().filter(|_| num_id == list_id || level > 0) {
+                    last.push('\n');
+                    last.push_str(&block);
+                    return;
+                }
+            }
+            if list_item.is_none() {
+                self.list_counters.clear();
+            }
+            self.list_id = list_item.map(|(num_id, _)| num_id);
+            self.blocks.push(block);
+        }
+
+        fn heading_level(&self, paragraph: &Paragraph) -> Option<usize> {
+            let property = paragraph.property.as_ref()?;
+            if let Some(style_id) = &property.style_id {
+                let name = self
+                    .style_names
+                    .get(style_id.value.as_ref())
+                    .cloned()
+                    .unwrap_or_else(|| style_id.value.to_lowercase());
+                if name == "title" {
+                    return Some(1);
+                }
+                if let Some(level) = name
+                    .strip_prefix("heading")
+                    .and_then(|level| level.trim().parse::<usize>().ok())
+                {
+                    return Some(level.clamp(1, 6));
+                }
+            }
+            property
+                .outline_lvl
+                .as_ref()
+                .filter(|outline| (0..9).contains(&outline.value))
+                .map(|outline| (outline.value as usize + 1).min(6))
+        }
+
+        fn is_bullet(&self, num_id: isize, level: isize) -> bool {
+            let Some(numbering) = &self.docx.numbering else {
+                return true;
+            };
+            let format = numbering
+                .numberings
+                .iter()
+                .find(|num| num.num_id == Some(num_id))
+                .and_then(|num| num.abstract_num_id.as_ref())
+                .and_then(|abstract_id| {
+                    numbering
+                        .abstract_numberings
+                        .iter()
+                        .find(|abstract_num| abstract_num.abstract_num_id == abstract_id.value)
+                })
+                .and_then(|abstract_num| abstract_num.levels.iter().find(|lvl| lvl.i_level == Some(level)))
+                .and_then(|lvl| lvl.number_format.as_ref())
+                .map(|format| format.value.to_string());
+            matches!(format.as_deref(), None | Some("bullet") | Some("none"))
+        }
+
+        fn list_marker(&mut self, paragraph: &Paragraph) -> Option<(String, isize, isize)> {
+            let numbering = paragraph.property.as_ref()?.numbering.as_ref()?;
+            let num_id = numbering.id.as_ref()?.value;
+            if num_id == 0 {
+                return None; // numId 0 explicitly removes numbering
+            }
+            let level = numbering.level.as_ref().map_or(0, |level| level.value).max(0);
+            let indent = "    ".repeat(level as usize);
+
+            // a shallower item restarts numbering of any deeper ones
+            self.list_counters.retain(|&(id, lvl), _| id != num_id || lvl <= level);
+            let marker = if self.is_bullet(num_id, level) {
+                format!("{indent}- ")
+            } else {
+                let counter = self.list_counters.entry((num_id, level)).or_insert(0);
+                *counter += 1;
+                format!("{indent}{counter}. ")
+            };
+            Some((marker, num_id, level))
+        }
+
+        fn run_segments(run: &Run, segments: &mut Vec<(String, Emphasis)>) {
+            let property: Option<&CharacterProperty> = run.property.as_ref();
+            // toggle properties such as <w:b/> are on when present, unless explicitly set to false
+            let on = |value: Option<Option<bool>>| value.is_some_and(|value| value.unwrap_or(true));
+            if on(property.and_then(|p| p.vanish.as_ref().map(|v| v.value))) {
+                return; // hidden text
+            }
+            let emphasis = Emphasis {
+                bold: on(property.and_then(|p| p.bold.as_ref().map(|b| b.value))),
+                italic: on(property.and_then(|p| p.italics.as_ref().map(|i| i.value))),
+                strike: on(property.and_then(|p| p.strike.as_ref().map(|s| s.value))),
+            };
+            for content in &run.content {
+                let text = match content {
+                    RunContent::Text(text) => escape_markdown(&text.text),
+                    RunContent::Tab(_) | RunContent::PTab(_) => "\t".to_string(),
+                    RunContent::Break(_) | RunContent::CarriageReturn(_) => "  \n".to_string(),
+                    RunContent::NoBreakHyphen(_) => "-".to_string(),
+                    _ => continue,
+                };
+                segments.push((text, emphasis));
+            }
+        }
+
+        fn inline_markdown(&self, paragraph: &Paragraph) -> String {
+            let mut segments: Vec<(String, Emphasis)> = vec![];
+            for content in &paragraph.content {
+                match content {
+                    ParagraphContent::Run(run) => Self::run_segments(run, &mut segments),
+                    ParagraphContent::Link(link) => {
+                        let text: String = link.iter_text().map(|text| escape_markdown(text)).collect();
+                        let target = match (&link.id, &link.anchor) {
+                            (Some(id), _) => self
+                                .docx
+                                .document_rels
+                                .as_ref()
+                                .and_then(|rels| rels.get_target(id))
+                                .map(|target| target.to_string()),
+                            (None, Some(anchor)) => Some(format!("#{anchor}")),
+                            (None, None) => None,
+                        };
+                        let text = match target {
+                            Some(target) if !text.trim().is_empty() => format!("[{text}]({target})"),
+                            _ => text,
+                        };
+                        segments.push((text, Emphasis::default()));
+                    }
+                    ParagraphContent::SDT(sdt) => {
+                        for body_content in sdt.content.iter().flat_map(|content| &content.content) {
+                            match body_content {
+                                BodyContent::Run(run) => Self::run_segments(run, &mut segments),
+                                BodyContent::Paragraph(para) => {
+                                    segments.push((self.inline_markdown(para), Emphasis::default()))
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            // merge adjacent segments with the same emphasis, so that we don't get e.g. **a****b**
+            let mut merged: Vec<(String, Emphasis)> = vec![];
+            for (text, emphasis) in segments {
+                match merged.last_mut() {
+                    Some((last_text, last_emphasis)) if *last_emphasis == emphasis => last_text.push_str(&text),
+                    _ => merged.push((text, emphasis)),
+                }
+            }
+            merged
+                .iter()
+                .map(|(text, emphasis)| emphasise(text, *emphasis))
+                .collect()
+        }
+
+        fn paragraph(&mut self, paragraph: &Paragraph) {
+            let text = self.inline_markdown(paragraph);
+            let text = text.trim();
+            if text.is_empty() {
+                return;
+            }
+            if let Some(level) = self.heading_level(paragraph) {
+                self.push_block(format!("{} {}", "#".repeat(level), text), None);
+            } else if let Some((marker, num_id, level)) = self.list_marker(paragraph) {
+                self.push_block(format!("{marker}{text}"), Some((num_id, level)));
+            } else {
+                self.push_block(text.to_string(), None);
+            }
+        }
+
+        fn table(&mut self, table: &Table) {
+            let rows: Vec<Vec<String>> = table
+                .rows
+                .iter()
+                .map(|row| {
+                    row.cells
+                        .iter()
+                        .filter_map(|cell| match cell {
+                            TableRowContent::TableCell(cell) => Some(
+                                cell.content
+                                    .iter()
+                                    .map(|TableCellContent::Paragraph(para)| self.inline_markdown(para))
+                                    .map(|text| text.trim().replace('|', "\\|").replace('\n', " "))
+                                    .filter(|text| !text.is_empty())
+                                    .collect::<Vec<_>>()
+                                    .join("<br>"),
+                            ),
+                            TableRowContent::SDT(_) => None,
+                        })
+                        .collect()
+                })
+                .collect();
+            let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+            if columns == 0 {
+                return;
+            }
+
+            let line = |cells: &[String]| {
+                let padding = vec![String::new(); columns - cells.len()];
+                format!(
+                    "| {} |",
+                    cells
+                        .iter()
+                        .chain(padding.iter())
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                )
+            };
+            let mut lines = vec![line(&rows[0]), line(&vec!["---".to_string(); columns])];
+            lines.extend(rows[1..].iter().map(|row| line(row)));
+            self.push_block(lines.join("\n"), None);
+        }
+
+        fn body_content(&mut self, content: &BodyContent) {
+            match content {
+                BodyContent::Paragraph(paragraph) => self.paragraph(paragraph),
+                BodyContent::Table(table) => self.table(table),
+                BodyContent::Sdt(sdt) => {
+                    for content in sdt.content.iter().flat_map(|content| &content.content) {
+                        self.body_content(content);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub(crate) fn docx_markdown(document: Vec<u8>) -> String {
+        with_docx(document, |docx| {
+            let mut writer = MarkdownWriter::new(docx);
+            for content in &docx.document.body.content {
+                writer.body_content(content);
+            }
+            writer.blocks.join("\n\n")
+        })
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn markdown_from_docx(document: Vec<u8>) -> String {
+        worker::extract_value("markdown_from_docx", document, &[])
+    }
+
+    // images
+
+    const NS_DRAWING: &str = "http://schemas.openxmlformats.org/drawingml/2006/main";
+    const NS_OFFICE: &str = "urn:schemas-microsoft-com:office:office";
+    const NS_WORD_DRAWING: &str = "http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing";
+    const NS_RELATIONSHIPS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+    const NS_VML: &str = "urn:schemas-microsoft-com:vml";
+    const NS_WORD: &str = "http://schemas.openxmlformats.org/wordprocessingml/2006/main";
+
+    fn image_mime_type(path: &str) -> &'static str {
+        let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+        match extension.to_lowercase().as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" | "jpe" => "image/jpeg",
+            "gif" => "image/gif",
+            "bmp" => "image/bmp",
+            "tif" | "tiff" => "image/tiff",
+            "svg" => "image/svg+xml",
+            "emf" => "image/emf",
+            "wmf" => "image/wmf",
+            _ => "application/octet-stream",
+        }
+    }
+
+    // reads the pixel dimensions from the headers of PNG, GIF and JPEG images
+    fn image_size(data: &[u8]) -> Option<(i32, i32)> {
+        let u16_be = |at: usize| Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?) as i32);
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            let u32_be = |at: usize| i32::try_from(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?)).ok();
+            return Some((u32_be(16)?, u32_be(20)?));
+        }
+        if data.starts_with(b"GIF8") {
+            let u16_le = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?) as i32);
+            return Some((u16_le(6)?, u16_le(8)?));
+        }
+        if !data.starts_with(&[0xff, 0xd8]) {
+            return None;
+        }
+        // walk the JPEG's segments to the start of frame, which holds the size (DHT, JPG and DAC markers share its range)
+        let mut at = 2;
+        while *data.get(at)? == 0xff {
+            let marker = *data.get(at + 1)?;
+            if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+                return Some((u16_be(at + 7)?, u16_be(at + 5)?));
+            }
+            at += 2 + u16_be(at + 2)? as usize;
+        }
+        None
+    }
+
+    pub(crate) fn docx_images(document: Vec<u8>) -> Vec<ImageRow> {
+        let mut docx = Package::new(document, ".docx file");
+        let document_path = "word/document.xml";
+        let document_xml = docx
+            .read(document_path)
+            .unwrap_or_pg_err("Couldn't find document in .docx file");
+        let document_rels = docx.relationships(document_path);
+        let document = docx.parse(&document_xml, document_path);
+
+        // DrawingML pictures give their alt text in the drawing's docPr, and legacy VML ones in the shape's title
+        let images: Vec<(&str, Option<&str>)> = document
+            .descendants()
+            .filter_map(|node| {
+                if node.has_tag_name((NS_WORD, "drawing")) {
+                    let blip = node
+                        .descendants()
+                        .find(|node| node.has_tag_name((NS_DRAWING, "blip")))?;
+                    let alt_text = node
+                        .descendants()
+                        .find(|node| node.has_tag_name((NS_WORD_DRAWING, "docPr")))
+                        .and_then(|doc_pr| doc_pr.attribute("descr"));
+                    Some((blip.attribute((NS_RELATIONSHIPS, "embed"))?, alt_text))
+                } else if node.has_tag_name((NS_VML, "imagedata")) {
+                    let alt_text = node.attribute((NS_OFFICE, "title"));
+                    Some((node.attribute((NS_RELATIONSHIPS, "id"))?, alt_text))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let mut rows = Vec::new();
+        for (id, alt_text) in images {
+            let Some((_, target)) = document_rels.get(id) else {
+                continue;
+            };
+            // linked rather than embedded images aren't in the package
+            let Some(data) = docx.read_bytes(target) else {
+                continue;
+            };
+            let size = image_size(&data);
+            rows.push((
+                rows.len() as i32 + 1,
+                image_mime_type(target).to_string(),
+                size.map(|(width, _)| width),
+                size.map(|(_, height)| height),
+                alt_text.filter(|alt_text| !alt_text.is_empty()).map(str::to_string),
+                data,
+            ));
+        }
+        rows
+    }
+
+    #[allow(clippy::type_complexity)]
+    #[pg_extern(immutable, strict)]
+    pub fn images_from_docx(
+        document: Vec<u8>,
+    ) -> TableIterator<
+        'static,
+        (
+            name!(page_or_index, i32),
+            name!(mime_type, String),
+            name!(width, Option<i32>),
+            name!(height, Option<i32>),
+            name!(alt_text, Option<String>),
+            name!(data, Vec<u8>),
+        ),
+    > {
+        TableIterator::new(worker::extract("images_from_docx", document, &[]))
     }
 }
 
@@ -39,4 +603,83 @@ mod tests {
     fn test_text_from_not_docx() {
         text_from_docx(include_bytes!("../test_res/test.pages").to_vec());
     }
+
+    #[pg_test]
+    fn test_text_from_docx_parts() {
+        let parts = text_from_docx_parts(include_bytes!("../test_res/test.docx").to_vec()).0;
+        assert_eq!(
+            parts["body"].as_str(),
+            Some(text_from_docx(include_bytes!("../test_res/test.docx").to_vec()).as_str())
+        );
+        assert_eq!(parts["headers"], serde_json::json!(["Header text"]));
+        assert_eq!(parts["footers"], serde_json::json!(["1\r\nFooter text"]));
+        assert_eq!(parts["footnotes"], serde_json::json!(["This is a footnote"]));
+        assert_eq!(parts["endnotes"], serde_json::json!([]));
+        assert_eq!(parts["comments"], serde_json::json!([]));
+    }
+
+    #[pg_test]
+    fn test_docx_part_order() {
+        let mut names = vec!["word/header10.xml", "word/header2.xml", "word/header.xml", "word/header1.xml"];
+        names.sort_by_key(|name| part_order(name));
+        assert_eq!(
+            names,
+            vec!["word/header1.xml", "word/header2.xml", "word/header10.xml", "word/header.xml"]
+        );
+    }
+
+    #[pg_test(error = "[rag] Couldn't read .docx file: Zip(FileNotFound)")]
+    fn test_text_from_not_docx_parts() {
+        text_from_docx_parts(include_bytes!("../test_res/test.pages").to_vec());
+    }
+
+    #[pg_test]
+    fn test_markdown_from_docx() {
+        assert_eq!(
+            markdown_from_docx(include_bytes!("../test_res/test_structured.docx").to_vec()),
+            "# Introduction\n\nSome **bold** and _italic_ text with a [link](https://example.com).\n\n## Lists\n\n- Apple\n    - Green apple\n- Banana\n\n1. First\n2. Second\n\n## Table\n\n| Name | Value |\n| --- | --- |\n| a | 1 |\n| b\\|c | 2 |"
+        )
+    }
+
+    #[pg_test]
+    fn test_markdown_from_plain_docx() {
+        assert_eq!(
+            markdown_from_docx(include_bytes!("../test_res/test.docx").to_vec()),
+            "Test .docx document\n\nLorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat.\n\n- One\n- Two\n- Three\n\nDuis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum."
+        )
+    }
+
+    #[pg_test(error = "[rag] Couldn't read .docx file: Zip(FileNotFound)")]
+    fn test_markdown_from_not_docx() {
+        markdown_from_docx(include_bytes!("../test_res/test.pages").to_vec());
+    }
+
+    #[pg_test]
+    fn test_images_from_docx() {
+        let images: Vec<_> = images_from_docx(include_bytes!("../test_res/test.docx").to_vec()).collect();
+        assert_eq!(images.len(), 1);
+        let (index, mime_type, width, height, alt_text, data) = &images[0];
+        assert_eq!(
+            (*index, mime_type.as_str(), *width, *height),
+            (1, "image/png", Some(1253), Some(1600))
+        );
+        assert_eq!(
+            alt_text.as_deref(),
+            Some("A blue and white photo of a tree\n\nDescription automatically generated")
+        );
+        assert_eq!(data.len(), 2908311);
+    }
+
+    #[pg_test]
+    fn test_images_from_docx_without_images() {
+        assert_eq!(
+            images_from_docx(include_bytes!("../test_res/test_structured.docx").to_vec()).count(),
+            0
+        );
+    }
+
+    #[pg_test(error = "[rag] Couldn't find document in .docx file")]
+    fn test_images_from_not_docx() {
+        images_from_docx(include_bytes!("../test_res/test.pages").to_vec());
+    }
 }
@@ -0,0 +1,192 @@
+use pgrx::prelude::*;
+
+#[pg_schema]
+pub(crate) mod rag {
+    use super::super::errors::*;
+    use super::super::limits::check_document_size;
+    use super::super::markdown::html_to_markdown;
+    use super::super::worker;
+    use mail_parser::mailbox::mbox::MessageIterator;
+    use mail_parser::{Address, Message, MessageParser, MimeHeaders, PartType};
+    use pgrx::prelude::*;
+    use serde_json::{json, Value};
+
+    fn parse(document: &[u8]) -> Message<'_> {
+        check_document_size(document);
+        // a message copied out of an mbox file may still start with its "From " separator line
+        let document = match document.starts_with(b"From ") {
+            true => document
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(&document[..0], |newline| &document[newline + 1..]),
+            false => document,
+        };
+        MessageParser::default()
+            .parse(document)
+            // the parser is lenient enough to accept almost anything, so insist on some of the usual headers
+            .filter(|message| {
+                message.from().is_some()
+                    || message.to().is_some()
+                    || message.subject().is_some()
+                    || message.date().is_some()
+                    || message.message_id().is_some()
+            })
+            .unwrap_or_pg_err("Couldn't parse email: no message headers found")
+    }
+
+    fn addresses(address: Option<&Address>) -> Value {
+        address
+            .map(|address| {
+                address
+                    .iter()
+                    .map(|addr| json!({"name": addr.name(), "address": addr.address()}))
+                    .collect()
+            })
+            .unwrap_or_else(|| json!([]))
+    }
+
+    // prefer text/plain, but convert HTML to Markdown where that's all there is
+    fn body(message: &Message) -> String {
+        message
+            .text_bodies()
+            .filter_map(|part| match &part.body {
+                PartType::Text(text) => Some(text.replace("\r\n", "\n").trim().to_string()),
+                PartType::Html(html) => Some(html_to_markdown(html)),
+                _ => None,
+            })
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    pub(crate) fn email_fields(document: &[u8]) -> pgrx::JsonB {
+        let message = parse(document);
+        pgrx::JsonB(json!({
+            "from": addresses(message.from()),
+            "to": addresses(message.to()),
+            "cc": addresses(message.cc()),
+            "subject": message.subject(),
+            "date": message.date().map(|date| date.to_rfc3339()),
+            "message_id": message.message_id(),
+            "in_reply_to": message.in_reply_to().as_text(),
+            "body": body(&message),
+        }))
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn parse_email(document: &[u8]) -> pgrx::JsonB {
+        worker::extract_value("parse_email", document.to_vec(), &[])
+    }
+
+    pub(crate) fn email_attachment_rows(document: &[u8]) -> Vec<(Option<String>, Option<String>, Vec<u8>)> {
+        let message = parse(document);
+        message
+            .attachments()
+            .map(|part| {
+                let content_type = part.content_type().map(|content_type| match content_type.subtype() {
+                    Some(subtype) => format!("{}/{}", content_type.ctype(), subtype),
+                    None => content_type.ctype().to_string(),
+                });
+                (
+                    part.attachment_name().map(str::to_string),
+                    content_type,
+                    part.contents().to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn email_attachments(
+        document: &[u8],
+    ) -> TableIterator<
+        'static,
+        (
+            name!(filename, Option<String>),
+            name!(content_type, Option<String>),
+            name!(content, Vec<u8>),
+        ),
+    > {
+        TableIterator::new(worker::extract("email_attachments", document.to_vec(), &[]))
+    }
+
+    // each message is a 1-tuple, since that's what a row of the result is
+    pub(crate) fn mbox_messages(document: &[u8]) -> Vec<(Vec<u8>,)> {
+        check_document_size(document);
+        MessageIterator::new(document)
+            .map(|message| (message.expect_or_pg_err("Couldn't read mbox").unwrap_contents(),))
+            .collect()
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn emails_from_mbox(document: &[u8]) -> SetOfIterator<'static, Vec<u8>> {
+        let messages: Vec<(Vec<u8>,)> = worker::extract("emails_from_mbox", document.to_vec(), &[]);
+        SetOfIterator::new(messages.into_iter().map(|(message,)| message))
+    }
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+    use serde_json::json;
+
+    #[pg_test]
+    fn test_parse_email() {
+        assert_eq!(
+            parse_email(include_bytes!("../test_res/test.eml")).0,
+            json!({
+                "from": [{"name": "Ada Lovelace", "address": "ada@example.com"}],
+                "to": [{"name": null, "address": "support@example.com"}],
+                "cc": [],
+                "subject": "Engine trouble",
+                "date": "2024-08-22T20:05:55+01:00",
+                "message_id": "second@example.com",
+                "in_reply_to": "first@example.com",
+                "body": "The engine won't start.\n\nSee the attached notes.",
+            })
+        );
+    }
+
+    #[pg_test]
+    fn test_parse_html_email() {
+        let email = b"From: a@example.com\r\nSubject: Hi\r\nContent-Type: text/html\r\n\r\n<h1>Hello</h1><p>There</p>";
+        assert_eq!(parse_email(email).0["body"], json!("# Hello\n\nThere"));
+    }
+
+    #[pg_test]
+    fn test_email_attachments() {
+        let attachments: Vec<_> = email_attachments(include_bytes!("../test_res/test.eml")).collect();
+        assert_eq!(
+            attachments,
+            vec![(
+                Some("notes.txt".to_string()),
+                Some("text/plain".to_string()),
+                b"Check the spark plugs.".to_vec()
+            )]
+        );
+    }
+
+    #[pg_test]
+    fn test_emails_from_mbox() {
+        let eml = include_bytes!("../test_res/test.eml");
+        let mbox = [
+            b"From ada@example.com Thu Aug 22 20:05:55 2024\n".as_slice(),
+            eml,
+            b"\n",
+        ]
+        .concat();
+        let mbox = [mbox.as_slice(), mbox.as_slice()].concat();
+        let emails: Vec<_> = emails_from_mbox(&mbox).collect();
+        assert_eq!(emails.len(), 2);
+        assert_eq!(parse_email(&emails[1]).0["subject"], json!("Engine trouble"));
+    }
+
+    #[pg_test(error = "[rag] Couldn't parse email: no message headers found")]
+    fn test_parse_not_email() {
+        parse_email(include_bytes!("../test_res/test.pdf"));
+    }
+}
@@ -0,0 +1,106 @@
+use pgrx::prelude::*;
+
+#[pg_schema]
+pub(crate) mod rag {
+    use super::super::errors::*;
+    use super::super::markdown::html_to_markdown;
+    use super::super::package::*;
+    use super::super::worker;
+    use pgrx::prelude::*;
+    use std::collections::HashMap;
+
+    // manifest hrefs are URLs, so file names may be percent-encoded
+    fn percent_decode(href: &str) -> String {
+        let bytes = href.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+            match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+                (b'%', Some(byte)) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                (byte, _) => {
+                    decoded.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
+    }
+
+    pub(crate) fn epub_chapters(document: Vec<u8>) -> Vec<(i32, String)> {
+        let mut epub = Package::new(document, ".epub file");
+
+        let container_path = "META-INF/container.xml";
+        let container_xml = epub
+            .read(container_path)
+            .unwrap_or_pg_err("Couldn't find container in .epub file");
+        let container = epub.parse(&container_xml, container_path);
+        let package_path = container
+            .descendants()
+            .find(|node| node.has_tag_name("rootfile"))
+            .and_then(|node| node.attribute("full-path"))
+            .unwrap_or_pg_err("Couldn't find package document in .epub file")
+            .to_string();
+
+        let package_xml = epub
+            .read(&package_path)
+            .unwrap_or_pg_err("Couldn't find package document in .epub file");
+        let package = epub.parse(&package_xml, &package_path);
+        let manifest: HashMap<&str, &str> = package
+            .descendants()
+            .filter(|node| node.has_tag_name("item"))
+            .filter_map(|node| Some((node.attribute("id")?, node.attribute("href")?)))
+            .collect();
+
+        // the spine lists the chapters in reading order, which needn't match the manifest order
+        let chapter_paths: Vec<String> = package
+            .descendants()
+            .filter(|node| node.has_tag_name("itemref"))
+            .filter_map(|node| manifest.get(node.attribute("idref")?))
+            .map(|href| resolve_path(&package_path, &percent_decode(href.split('#').next().unwrap_or(href))))
+            .collect();
+
+        let mut chapters = Vec::new();
+        for (index, chapter_path) in chapter_paths.iter().enumerate() {
+            let xhtml = epub
+                .read(chapter_path)
+                .unwrap_or_pg_err("Couldn't find chapter in .epub file");
+            chapters.push((index as i32 + 1, html_to_markdown(&xhtml)));
+        }
+        chapters
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn text_from_epub(document: Vec<u8>) -> TableIterator<'static, (name!(chapter, i32), name!(text, String))> {
+        TableIterator::new(worker::extract("text_from_epub", document, &[]))
+    }
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_text_from_epub() {
+        let chapters: Vec<_> = text_from_epub(include_bytes!("../test_res/test.epub").to_vec()).collect();
+        assert_eq!(
+            chapters,
+            vec![
+                (1, "# Chapter One\n\nIt was a _dark_ and stormy night.".to_string()),
+                (2, "# Chapter Two\n\nThe end.".to_string()),
+            ]
+        );
+    }
+
+    #[pg_test(error = "[rag] Couldn't find container in .epub file")]
+    fn test_text_from_not_epub() {
+        text_from_epub(include_bytes!("../test_res/test.docx").to_vec());
+    }
+}
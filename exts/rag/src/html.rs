@@ -0,0 +1,674 @@
+// HTML parsing and traversal, using the same parser (html5ever) as the htmd Markdown converter
+
+use crate::errors::*;
+use html5ever::parse_document;
+use html5ever::serialize::{serialize, SerializeOpts, TraversalScope};
+use html5ever::tendril::TendrilSink;
+use markup5ever_rcdom::{Handle, Node, NodeData, RcDom, SerializableHandle};
+use pgrx::prelude::*;
+use std::rc::Rc;
+
+pub fn parse_html(document: &str) -> RcDom {
+    parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut document.as_bytes())
+        .expect_or_pg_err("Error parsing HTML")
+}
+
+// serializes the node itself, unless it's the document, which has no tag of its own
+pub fn serialize_html(node: &Handle) -> String {
+    let traversal_scope = match node.data {
+        NodeData::Document => TraversalScope::ChildrenOnly(None),
+        _ => TraversalScope::IncludeNode,
+    };
+    let mut html = Vec::new();
+    serialize(
+        &mut html,
+        &SerializableHandle::from(node.clone()),
+        SerializeOpts {
+            traversal_scope,
+            ..Default::default()
+        },
+    )
+    .expect_or_pg_err("Error serializing HTML");
+    String::from_utf8_lossy(&html).into_owned()
+}
+
+pub fn element_name(node: &Handle) -> Option<&str> {
+    match &node.data {
+        NodeData::Element { name, .. } => Some(name.local.as_ref()),
+        _ => None,
+    }
+}
+
+pub fn attribute(node: &Handle, attribute: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| attr.name.local.as_ref() == attribute)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+// the parent is a weak reference in a Cell, so it has to be taken out to be upgraded
+pub fn parent(node: &Handle) -> Option<Handle> {
+    let parent = node.parent.take();
+    node.parent.set(parent.clone());
+    parent?.upgrade()
+}
+
+pub fn text_content(node: &Handle) -> String {
+    fn push_text(node: &Handle, text: &mut String) {
+        match &node.data {
+            NodeData::Text { contents } => text.push_str(&contents.borrow()),
+            _ => node.children.borrow().iter().for_each(|child| push_text(child, text)),
+        }
+    }
+    let mut text = String::new();
+    push_text(node, &mut text);
+    text
+}
+
+// text with runs of whitespace collapsed to single spaces, as a browser would display it
+pub fn normalized_text(node: &Handle) -> String {
+    text_content(node).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn descendant_elements(node: &Handle, elements: &mut Vec<Handle>) {
+    for child in node.children.borrow().iter() {
+        if element_name(child).is_some() {
+            elements.push(child.clone());
+        }
+        descendant_elements(child, elements);
+    }
+}
+
+#[pg_schema]
+mod rag {
+    use super::super::markdown::html_to_markdown;
+    use super::*;
+    use serde_json::{json, Map, Value};
+    use std::collections::{HashMap, HashSet};
+    use url::Url;
+
+    // main content, after Arc90's Readability: paragraphs score points for the elements that contain them, and the
+    // highest-scoring element (discounted by the proportion of its text that's in links) is taken to be the article
+
+    // elements that are never part of an article's text
+    const BOILERPLATE_TAGS: [&str; 14] = [
+        "script", "style", "noscript", "template", "nav", "aside", "footer", "form", "iframe", "svg", "button",
+        "select", "textarea", "dialog",
+    ];
+    const BOILERPLATE_ROLES: [&str; 6] = [
+        "navigation",
+        "banner",
+        "contentinfo",
+        "complementary",
+        "dialog",
+        "alert",
+    ];
+    // matched against the words in class and id, so "nav" matches "main-nav" but not "canvas"
+    const UNLIKELY_NAMES: [&str; 24] = [
+        "advert",
+        "advertisement",
+        "banner",
+        "breadcrumb",
+        "breadcrumbs",
+        "comment",
+        "comments",
+        "consent",
+        "cookie",
+        "footer",
+        "header",
+        "menu",
+        "modal",
+        "nav",
+        "navbar",
+        "newsletter",
+        "popup",
+        "promo",
+        "related",
+        "share",
+        "sidebar",
+        "social",
+        "sponsor",
+        "subscribe",
+    ];
+    const LIKELY_NAMES: [&str; 8] = ["article", "body", "content", "entry", "main", "post", "story", "text"];
+    const BLOCK_TAGS: [&str; 21] = [
+        "address",
+        "article",
+        "aside",
+        "blockquote",
+        "div",
+        "dl",
+        "figure",
+        "footer",
+        "form",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "header",
+        "ol",
+        "p",
+        "pre",
+        "section",
+        "table",
+    ];
+    const MIN_PARAGRAPH_CHARS: usize = 25;
+
+    // words are separated by anything but letters and digits, as in "post-body" or "site_footer"
+    fn has_name(node: &Handle, names: &[&str]) -> bool {
+        let class = attribute(node, "class").unwrap_or_default();
+        let id = attribute(node, "id").unwrap_or_default();
+        format!("{class} {id}")
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| names.contains(&word))
+    }
+
+    fn name_weight(node: &Handle) -> f64 {
+        let mut weight = 0.0;
+        if has_name(node, &LIKELY_NAMES) {
+            weight += 25.0;
+        }
+        if has_name(node, &UNLIKELY_NAMES) {
+            weight -= 25.0;
+        }
+        weight
+    }
+
+    fn is_boilerplate(node: &Handle, in_article: bool) -> bool {
+        let Some(tag) = element_name(node) else {
+            return false;
+        };
+        if BOILERPLATE_TAGS.contains(&tag) || attribute(node, "hidden").is_some() {
+            return true;
+        }
+        if ["html", "body", "article", "main"].contains(&tag) {
+            return false;
+        }
+        // an article's own header holds its title, whereas a page header holds the site's name and menu
+        if tag == "header" && !in_article {
+            return true;
+        }
+        attribute(node, "role").is_some_and(|role| BOILERPLATE_ROLES.contains(&role.as_str()))
+    }
+
+    fn remove_boilerplate(node: &Handle, in_article: bool) {
+        node.children
+            .borrow_mut()
+            .retain(|child| !is_boilerplate(child, in_article));
+        let in_article = in_article || matches!(element_name(node), Some("article" | "main"));
+        for child in node.children.borrow().iter() {
+            remove_boilerplate(child, in_article);
+        }
+    }
+
+    // An element whose name suggests it isn't part of the article (a comments section, say) doesn't count towards
+    // choosing the article, but isn't removed from it either: a name that looks unlikely may be legitimate there. A
+    // name that also looks likely, as in "post-header", is given the benefit of the doubt.
+    fn is_unlikely(node: &Handle) -> bool {
+        element_name(node).is_some_and(|tag| !["html", "body", "article", "main"].contains(&tag))
+            && has_name(node, &UNLIKELY_NAMES)
+            && !has_name(node, &LIKELY_NAMES)
+    }
+
+    fn remove_unlikely(node: &Handle) {
+        node.children.borrow_mut().retain(|child| !is_unlikely(child));
+        for child in node.children.borrow().iter() {
+            remove_unlikely(child);
+        }
+    }
+
+    fn tag_weight(tag: &str) -> f64 {
+        match tag {
+            "article" | "main" => 10.0,
+            "div" => 5.0,
+            "pre" | "td" | "blockquote" => 3.0,
+            "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+            _ => 0.0,
+        }
+    }
+
+    fn is_paragraph(node: &Handle) -> bool {
+        match element_name(node) {
+            Some("p" | "pre" | "td" | "blockquote") => true,
+            // a div or section that contains only inline content is a paragraph in all but name
+            Some("div" | "section") => !node
+                .children
+                .borrow()
+                .iter()
+                .any(|child| element_name(child).is_some_and(|tag| BLOCK_TAGS.contains(&tag))),
+            _ => false,
+        }
+    }
+
+    fn link_density(node: &Handle) -> f64 {
+        let text_len = normalized_text(node).len();
+        if text_len == 0 {
+            return 0.0;
+        }
+        let mut elements = Vec::new();
+        descendant_elements(node, &mut elements);
+        let link_len: usize = elements
+            .iter()
+            .filter(|element| element_name(element) == Some("a"))
+            .map(|link| normalized_text(link).len())
+            .sum();
+        link_len as f64 / text_len as f64
+    }
+
+    struct Candidate {
+        node: Handle,
+        score: f64,
+    }
+
+    // scores the ancestors of each paragraph: the closer the ancestor, the larger its share of the paragraph's score
+    fn score_paragraphs(node: &Handle, ancestors: &mut Vec<Handle>, candidates: &mut HashMap<*const Node, Candidate>) {
+        if is_unlikely(node) {
+            return;
+        }
+        if is_paragraph(node) {
+            let text = normalized_text(node);
+            if text.len() >= MIN_PARAGRAPH_CHARS {
+                let score = 1.0 + text.matches(',').count() as f64 + (text.len() as f64 / 100.0).min(3.0);
+                for (level, ancestor) in ancestors.iter().rev().take(5).enumerate() {
+                    let divider = match level {
+                        0 => 1.0,
+                        1 => 2.0,
+                        _ => level as f64 * 3.0,
+                    };
+                    let candidate = candidates.entry(Rc::as_ptr(ancestor)).or_insert_with(|| Candidate {
+                        node: ancestor.clone(),
+                        score: tag_weight(element_name(ancestor).unwrap_or("")) + name_weight(ancestor),
+                    });
+                    candidate.score += score / divider;
+                }
+            }
+        }
+        if element_name(node).is_some() {
+            ancestors.push(node.clone());
+        }
+        for child in node.children.borrow().iter() {
+            score_paragraphs(child, ancestors, candidates);
+        }
+        if element_name(node).is_some() {
+            ancestors.pop();
+        }
+    }
+
+    // the top candidate's siblings are included too if they score well, look like paragraphs of prose or are headings
+    fn with_related_siblings(top: &Candidate, candidates: &HashMap<*const Node, Candidate>) -> Vec<Handle> {
+        let Some(parent) = parent(&top.node) else {
+            return vec![top.node.clone()];
+        };
+        let threshold = (top.score * 0.2).max(10.0);
+        let siblings: Vec<Handle> = parent.children.borrow().iter().cloned().collect();
+        siblings
+            .into_iter()
+            .filter(|sibling| {
+                if Rc::ptr_eq(sibling, &top.node) {
+                    return true;
+                }
+                if is_unlikely(sibling) {
+                    return false;
+                }
+                let Some(tag) = element_name(sibling) else {
+                    return false;
+                };
+                let score = candidates
+                    .get(&Rc::as_ptr(sibling))
+                    .map(|candidate| candidate.score * (1.0 - link_density(sibling)));
+                if score.is_some_and(|score| score >= threshold) {
+                    return true;
+                }
+                match tag {
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => true,
+                    "p" => {
+                        let text = normalized_text(sibling);
+                        let density = link_density(sibling);
+                        (text.len() > 80 && density < 0.25) || (density == 0.0 && text.contains(". "))
+                    }
+                    _ => false,
+                }
+            })
+            .collect()
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn main_content_from_html(document: &str) -> String {
+        let dom = parse_html(document);
+        remove_boilerplate(&dom.document, false);
+
+        let mut candidates = HashMap::new();
+        score_paragraphs(&dom.document, &mut vec![], &mut candidates);
+        let top = candidates
+            .values()
+            .map(|candidate| Candidate {
+                node: candidate.node.clone(),
+                score: candidate.score * (1.0 - link_density(&candidate.node)),
+            })
+            .max_by(|a, b| a.score.total_cmp(&b.score));
+
+        // with no paragraphs to go on, fall back to everything that isn't boilerplate
+        let html = match top {
+            Some(top) => with_related_siblings(&top, &candidates)
+                .iter()
+                .map(serialize_html)
+                .collect::<String>(),
+            None => {
+                remove_unlikely(&dom.document);
+                serialize_html(&dom.document)
+            }
+        };
+        html_to_markdown(&html)
+    }
+
+    // metadata
+
+    fn resolve_url(base_url: Option<&Url>, url: &str) -> Option<Url> {
+        match base_url {
+            Some(base_url) => base_url.join(url.trim()).ok(),
+            None => Url::parse(url.trim()).ok(),
+        }
+    }
+
+    // OpenGraph properties whose values are URLs
+    const OPENGRAPH_URLS: [&str; 10] = [
+        "url",
+        "image",
+        "image:url",
+        "image:secure_url",
+        "video",
+        "video:url",
+        "video:secure_url",
+        "audio",
+        "audio:url",
+        "audio:secure_url",
+    ];
+
+    fn has_rel(element: &Handle, rel: &str) -> bool {
+        attribute(element, "rel").is_some_and(|rels| rels.split_whitespace().any(|r| r.eq_ignore_ascii_case(rel)))
+    }
+
+    // anchor text, falling back to the link's label or title, or the alt text of an image inside it
+    fn link_text(link: &Handle) -> String {
+        let text = normalized_text(link);
+        if !text.is_empty() {
+            return text;
+        }
+        let mut elements = Vec::new();
+        descendant_elements(link, &mut elements);
+        attribute(link, "aria-label")
+            .or_else(|| attribute(link, "title"))
+            .or_else(|| {
+                elements
+                    .iter()
+                    .filter(|element| element_name(element) == Some("img"))
+                    .find_map(|img| attribute(img, "alt"))
+            })
+            .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default()
+    }
+
+    #[pg_extern(immutable)]
+    pub fn metadata_from_html(document: Option<&str>, base_url: default!(Option<&str>, "NULL")) -> Option<pgrx::JsonB> {
+        let document = document?;
+        let base_url = base_url.map(|base_url| Url::parse(base_url).expect_or_pg_err("Invalid base URL"));
+        let dom = parse_html(document);
+        let mut elements = Vec::new();
+        descendant_elements(&dom.document, &mut elements);
+        let elements_named = |tag: &'static str| {
+            elements
+                .iter()
+                .filter(move |element| element_name(element) == Some(tag))
+        };
+
+        // a <base> element changes the URL that relative URLs in the page are resolved against
+        let page_url = base_url.clone();
+        let base_url = elements_named("base")
+            .find_map(|base| attribute(base, "href"))
+            .and_then(|href| resolve_url(base_url.as_ref(), &href))
+            .or(base_url);
+        let base_url = base_url.as_ref();
+
+        let meta = |attr: &str, key: &str| {
+            elements_named("meta")
+                .filter(|meta| attribute(meta, attr).is_some_and(|value| value.eq_ignore_ascii_case(key)))
+                .find_map(|meta| attribute(meta, "content"))
+        };
+        let language = elements_named("html")
+            .find_map(|html| attribute(html, "lang"))
+            .or_else(|| meta("http-equiv", "content-language"))
+            .filter(|language| !language.trim().is_empty());
+        let canonical_url = elements_named("link")
+            .filter(|link| has_rel(link, "canonical"))
+            .find_map(|link| resolve_url(base_url, &attribute(link, "href")?));
+
+        // every og:image, since a page may offer several, and the first value of each other property; URLs that can't
+        // be made absolute are left as they are
+        let mut opengraph = Map::new();
+        let mut images = Vec::new();
+        for meta in elements_named("meta") {
+            let Some(property) = attribute(meta, "property") else {
+                continue;
+            };
+            let (Some(key), Some(mut content)) = (property.strip_prefix("og:"), attribute(meta, "content")) else {
+                continue;
+            };
+            if OPENGRAPH_URLS.contains(&key) {
+                if let Some(url) = resolve_url(base_url, &content) {
+                    content = url.into();
+                }
+            }
+            match key {
+                "image" => images.push(content),
+                _ => {
+                    opengraph.entry(key).or_insert(json!(content));
+                }
+            }
+        }
+        if !images.is_empty() {
+            opengraph.insert("image".to_string(), json!(images));
+        }
+
+        // blocks that aren't valid JSON are skipped
+        let json_ld: Vec<Value> = elements_named("script")
+            .filter(|script| {
+                attribute(script, "type").is_some_and(|ty| ty.trim().eq_ignore_ascii_case("application/ld+json"))
+            })
+            .filter_map(|script| serde_json::from_str(&text_content(script)).ok())
+            .collect();
+
+        // links are to web pages only (not mailto: etc.), without their #fragment, and listed once each, leaving out
+        // links within the page itself (such as #section), i.e. to its own, base or canonical URL
+        let mut seen: HashSet<String> = [page_url.as_ref(), base_url, canonical_url.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|url| {
+                let mut url = url.clone();
+                url.set_fragment(None);
+                url.to_string()
+            })
+            .collect();
+        let mut links = Vec::new();
+        for link in elements_named("a") {
+            let Some(mut url) = attribute(link, "href").and_then(|href| resolve_url(base_url, &href)) else {
+                continue;
+            };
+            if !["http", "https"].contains(&url.scheme()) {
+                continue;
+            }
+            url.set_fragment(None);
+            if seen.insert(url.to_string()) {
+                links.push(json!({"url": url.as_str(), "text": link_text(link)}));
+            }
+        }
+
+        Some(pgrx::JsonB(json!({
+            "title": elements_named("title").next().map(normalized_text),
+            "description": meta("name", "description"),
+            "canonical_url": canonical_url.map(String::from),
+            "language": language,
+            "opengraph": opengraph,
+            "json_ld": json_ld,
+            "links": links,
+        })))
+    }
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+    use serde_json::json;
+
+    #[pg_test]
+    fn test_main_content_from_html() {
+        let html = r#"<html><head><title>Engines</title></head><body>
+            <header><a href="/">Home</a> <a href="/about">About</a></header>
+            <div class="cookie-banner">We use cookies, to improve your experience, and for analytics.</div>
+            <div class="layout">
+              <aside><h2>Popular</h2><ul><li><a href="/a">Something else entirely, with commas, in it</a></li></ul></aside>
+              <article>
+                <h1>The Analytical Engine</h1>
+                <p>The Analytical Engine was a proposed digital mechanical general-purpose computer, designed by Charles Babbage.</p>
+                <p>It was first described in 1837, as the successor to Babbage's Difference Engine, a simpler calculator.</p>
+              </article>
+              <div class="links"><a href="/1">Related article one</a> <a href="/2">Related article two, also good</a></div>
+            </div>
+            <footer>Copyright, all rights reserved, since the year 1999 at least.</footer>
+            </body></html>"#;
+        assert_eq!(
+            main_content_from_html(html),
+            "# The Analytical Engine\n\nThe Analytical Engine was a proposed digital mechanical general-purpose computer, designed by Charles Babbage.\n\nIt was first described in 1837, as the successor to Babbage's Difference Engine, a simpler calculator."
+        );
+    }
+
+    #[pg_test]
+    fn test_main_content_from_html_without_article() {
+        let html = r#"<body><nav><a href="/">Home</a></nav>
+            <div id="content"><h2>Notes</h2>
+              <div>First paragraph of the notes, which is long enough to count.</div>
+              <div>Second paragraph of the notes, which is also long enough.</div>
+            </div>
+            <div id="sidebar">A sidebar paragraph that should not be included, though it's long.</div></body>"#;
+        assert_eq!(
+            main_content_from_html(html),
+            "## Notes\n\nFirst paragraph of the notes, which is long enough to count.\n\nSecond paragraph of the notes, which is also long enough."
+        );
+    }
+
+    #[pg_test]
+    fn test_main_content_from_html_unlikely_names() {
+        // "canvas" contains "nav", but isn't the word nav; "post-footer" is likely as well as unlikely
+        let html = r#"<body>
+            <div class="canvas">
+              <p>A paragraph in an element whose class merely contains an unlikely name, which is fine.</p>
+              <div class="share-quote"><p>A pull quote, inside the article, whose class happens to look unlikely.</p></div>
+              <div class="post-footer"><p>A closing paragraph, whose class is both likely and unlikely, in the end.</p></div>
+            </div>
+            <div class="comments">
+              <p>A comment, which is long enough, and has commas, lots of commas, to score well.</p>
+              <p>Another comment, which is long enough, and has commas, lots of commas, to score well.</p>
+            </div></body>"#;
+        assert_eq!(
+            main_content_from_html(html),
+            "A paragraph in an element whose class merely contains an unlikely name, which is fine.\n\nA pull quote, inside the article, whose class happens to look unlikely.\n\nA closing paragraph, whose class is both likely and unlikely, in the end."
+        );
+    }
+
+    #[pg_test]
+    fn test_main_content_from_html_without_paragraphs() {
+        assert_eq!(main_content_from_html("<nav>Menu</nav><h1>Short</h1>"), "# Short");
+    }
+
+    #[pg_test]
+    fn test_metadata_from_html() {
+        let html = r##"<html lang="en-GB"><head>
+            <title> The Analytical
+              Engine </title>
+            <meta name="Description" content="A history of the engine">
+            <link rel="canonical" href="/engine">
+            <meta property="og:title" content="The Engine">
+            <meta property="og:url" content="/engine">
+            <meta property="og:image" content="https://example.com/1.png">
+            <meta property="og:image" content="images/2.png">
+            <script type="application/ld+json">{"@type": "Article", "headline": "The Engine"}</script>
+            <script type="application/ld+json">{not json</script>
+            </head><body>
+            <a href="notes.html#top">Notes</a> <a href="notes.html">Notes again</a>
+            <a href="https://other.example.org/"><img src="logo.png" alt="Other site"></a>
+            <a href="mailto:ada@example.com">Email</a> <a href="#section">Section</a> <a>No link</a>
+            <a href="/engine#history">Canonical</a>
+            </body></html>"##;
+        assert_eq!(
+            metadata_from_html(Some(html), Some("https://example.com/history/"))
+                .unwrap()
+                .0,
+            json!({
+                "title": "The Analytical Engine",
+                "description": "A history of the engine",
+                "canonical_url": "https://example.com/engine",
+                "language": "en-GB",
+                "opengraph": {
+                    "title": "The Engine",
+                    "url": "https://example.com/engine",
+                    "image": ["https://example.com/1.png", "https://example.com/history/images/2.png"],
+                },
+                "json_ld": [{"@type": "Article", "headline": "The Engine"}],
+                "links": [
+                    {"url": "https://example.com/history/notes.html", "text": "Notes"},
+                    {"url": "https://other.example.org/", "text": "Other site"},
+                ],
+            })
+        );
+    }
+
+    #[pg_test]
+    fn test_metadata_from_html_without_base_url() {
+        let html = r#"<meta property="og:image" content="/image.png">
+            <a href="/relative">Relative</a> <a href="https://example.com/">Absolute</a>"#;
+        assert_eq!(
+            metadata_from_html(Some(html), None).unwrap().0,
+            json!({
+                "title": null,
+                "description": null,
+                "canonical_url": null,
+                "language": null,
+                "opengraph": {"image": ["/image.png"]},
+                "json_ld": [],
+                "links": [{"url": "https://example.com/", "text": "Absolute"}],
+            })
+        );
+    }
+
+    #[pg_test]
+    fn test_metadata_from_html_with_base_element() {
+        let html = r#"<head><base href="https://example.com/docs/"></head><a href="intro.html">Intro</a>"#;
+        assert_eq!(
+            metadata_from_html(Some(html), None).unwrap().0["links"],
+            json!([{"url": "https://example.com/docs/intro.html", "text": "Intro"}])
+        );
+    }
+
+    #[pg_test(error = "[rag] Invalid base URL: relative URL without a base")]
+    fn test_metadata_from_html_with_relative_base_url() {
+        metadata_from_html(Some("<p>Hi</p>"), Some("/docs/"));
+    }
+
+    #[pg_test]
+    fn test_metadata_from_null_html() {
+        assert!(metadata_from_html(None, Some("https://example.com/")).is_none());
+    }
+}
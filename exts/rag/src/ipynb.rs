@@ -0,0 +1,209 @@
+use pgrx::prelude::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+// notebooks store multi-line text either as a string or as an array of lines (each ending in \n)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MultilineString {
+    Single(String),
+    Lines(Vec<String>),
+}
+
+impl MultilineString {
+    fn text(&self) -> String {
+        match self {
+            MultilineString::Single(s) => s.clone(),
+            MultilineString::Lines(lines) => lines.concat(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "output_type", rename_all = "snake_case")]
+enum Output {
+    Stream {
+        text: MultilineString,
+    },
+    ExecuteResult {
+        data: Map<String, Value>,
+    },
+    DisplayData {
+        data: Map<String, Value>,
+    },
+    Error {
+        ename: String,
+        evalue: String,
+    },
+    // e.g. update_display_data, which only appears in messages from the kernel
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "cell_type", rename_all = "lowercase")]
+enum Cell {
+    Markdown {
+        source: MultilineString,
+    },
+    Code {
+        source: MultilineString,
+        #[serde(default)]
+        outputs: Vec<Output>,
+    },
+    Raw {},
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct KernelSpec {
+    language: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct LanguageInfo {
+    name: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct NotebookMetadata {
+    kernelspec: KernelSpec,
+    language_info: LanguageInfo,
+}
+
+#[derive(Deserialize)]
+struct Notebook {
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: NotebookMetadata,
+}
+
+// the fence must be longer than any run of backticks in the code it encloses
+fn fenced(text: &str, language: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}{language}\n{}\n{fence}", text.trim_end_matches('\n'))
+}
+
+// Rich outputs are rendered as Markdown if they have a Markdown representation, and otherwise as plain text. Images
+// and other binary outputs are left out.
+fn output_markdown(output: &Output) -> Option<String> {
+    let text = match output {
+        Output::Stream { text } => text.text(),
+        Output::ExecuteResult { data } | Output::DisplayData { data } => {
+            let text = |mime_type| serde_json::from_value::<MultilineString>(data.get(mime_type)?.clone()).ok();
+            if let Some(markdown) = text("text/markdown") {
+                return Some(markdown.text().trim().to_string());
+            }
+            text("text/plain")?.text()
+        }
+        Output::Error { ename, evalue } => format!("{ename}: {evalue}"),
+        Output::Other => return None,
+    };
+    (!text.trim().is_empty()).then(|| fenced(&text, ""))
+}
+
+fn notebook_to_markdown(notebook: Notebook, include_outputs: bool) -> String {
+    let metadata = notebook.metadata;
+    let language = metadata
+        .kernelspec
+        .language
+        .or(metadata.language_info.name)
+        .unwrap_or_default()
+        .to_lowercase();
+    let mut blocks: Vec<String> = vec![];
+    for cell in notebook.cells {
+        match cell {
+            Cell::Markdown { source } => blocks.push(source.text().trim().to_string()),
+            Cell::Code { source, outputs } => {
+                let source = source.text();
+                if !source.trim().is_empty() {
+                    blocks.push(fenced(&source, &language));
+                }
+                if include_outputs {
+                    blocks.extend(outputs.iter().filter_map(output_markdown));
+                }
+            }
+            Cell::Raw {} => {}
+        }
+    }
+    blocks.retain(|block| !block.is_empty());
+    blocks.join("\n\n")
+}
+
+#[pg_schema]
+mod rag {
+    use super::super::errors::*;
+    use super::notebook_to_markdown;
+    use pgrx::prelude::*;
+
+    #[pg_extern(immutable, strict)]
+    pub fn markdown_from_ipynb(document: pgrx::JsonB, include_outputs: default!(bool, "false")) -> String {
+        let notebook = serde_json::from_value(document.0).expect_or_pg_err("Invalid Jupyter notebook");
+        notebook_to_markdown(notebook, include_outputs)
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+    use serde_json::json;
+
+    fn notebook() -> pgrx::JsonB {
+        pgrx::JsonB(serde_json::from_str(include_str!("../test_res/test.ipynb")).unwrap())
+    }
+
+    #[pg_test]
+    fn test_markdown_from_ipynb() {
+        assert_eq!(
+            markdown_from_ipynb(notebook(), false),
+            "# Fibonacci\n\nComputes the *Fibonacci* sequence.\n\n\
+            ```python\ndef fib(n):\n    return n if n < 2 else fib(n - 1) + fib(n - 2)\n\n\
+            print(*[fib(i) for i in range(5)])\nfib(5)\n```\n\n\
+            ```python\nplot(fib)\n1 / 0\n```"
+        )
+    }
+
+    #[pg_test]
+    fn test_markdown_from_ipynb_with_outputs() {
+        assert_eq!(
+            markdown_from_ipynb(notebook(), true),
+            "# Fibonacci\n\nComputes the *Fibonacci* sequence.\n\n\
+            ```python\ndef fib(n):\n    return n if n < 2 else fib(n - 1) + fib(n - 2)\n\n\
+            print(*[fib(i) for i in range(5)])\nfib(5)\n```\n\n\
+            ```\n0 1 1 2 3\n```\n\n```\n5\n```\n\n\
+            ```python\nplot(fib)\n1 / 0\n```\n\n\
+            ```\n<Figure size 640x480 with 1 Axes>\n```\n\n```\nZeroDivisionError: division by zero\n```"
+        )
+    }
+
+    #[pg_test]
+    fn test_markdown_from_ipynb_fences() {
+        let notebook = json!({
+            "cells": [
+                {"cell_type": "code", "source": "print('```')", "outputs": [
+                    {"output_type": "execute_result", "data": {"text/markdown": "**bold**", "text/plain": "bold"}}
+                ]},
+            ],
+            "metadata": {},
+        });
+        assert_eq!(
+            markdown_from_ipynb(pgrx::JsonB(notebook), true),
+            "````\nprint('```')\n````\n\n**bold**"
+        )
+    }
+
+    #[pg_test(error = "[rag] Invalid Jupyter notebook: missing field `cells`")]
+    fn test_markdown_from_not_ipynb() {
+        markdown_from_ipynb(pgrx::JsonB(json!({"worksheets": []})), false);
+    }
+}
@@ -0,0 +1,94 @@
+use pgrx::prelude::*;
+use serde_json::{json, Value};
+
+fn rounded(score: f64) -> Value {
+    json!((score * 10_000.0).round() / 10_000.0)
+}
+
+#[pg_schema]
+mod rag {
+    use super::rounded;
+    use pgrx::prelude::*;
+    use serde_json::json;
+
+    #[pg_extern(immutable, strict)]
+    pub fn detect_language(text: &str) -> Option<&'static str> {
+        whatlang::detect_lang(text).map(|lang| lang.code())
+    }
+
+    // whatlang gives the detected language's confidence, but not scores for the other candidates
+    #[pg_extern(immutable, strict)]
+    pub fn detect_language_scores(text: &str) -> Option<pgrx::JsonB> {
+        let info = whatlang::detect(text)?;
+        Some(pgrx::JsonB(json!({
+            "language": info.lang().code(),
+            "name": info.lang().eng_name(),
+            "script": info.script().name(),
+            "confidence": rounded(info.confidence()),
+            "reliable": info.is_reliable(),
+        })))
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+
+    const ENGLISH: &str = "The quick brown fox jumps over the lazy dog, and then it runs back into the forest.";
+    const GERMAN: &str = "Der schnelle braune Fuchs springt über den faulen Hund und läuft dann zurück in den Wald.";
+
+    #[pg_test]
+    fn test_detect_language() {
+        assert_eq!(detect_language(ENGLISH), Some("eng"));
+        assert_eq!(detect_language(GERMAN), Some("deu"));
+        assert_eq!(
+            detect_language("Быстрая коричневая лиса прыгает через ленивую собаку."),
+            Some("rus")
+        );
+        assert_eq!(detect_language("敏捷的棕色狐狸跳过了懒狗"), Some("cmn"));
+        assert_eq!(detect_language("1234 !?"), None);
+    }
+
+    #[pg_test]
+    fn test_detect_language_scores() {
+        let scores = detect_language_scores(GERMAN).unwrap().0;
+        assert_eq!(scores["language"], "deu");
+        assert_eq!(scores["name"], "German");
+        assert_eq!(scores["script"], "Latin");
+        assert_eq!(scores["reliable"], true);
+        assert_eq!(scores["confidence"], 1.0);
+        assert_eq!(scores.as_object().unwrap().len(), 5);
+    }
+
+    #[pg_test]
+    fn test_detect_language_scores_short_text() {
+        // as in the README: a single short sentence is identified, but not reliably
+        let scores = detect_language_scores("Der schnelle braune Fuchs springt über den faulen Hund.")
+            .unwrap()
+            .0;
+        assert_eq!(
+            scores,
+            serde_json::json!({
+                "language": "deu",
+                "name": "German",
+                "script": "Latin",
+                "confidence": 0.4367,
+                "reliable": false,
+            })
+        );
+    }
+
+    #[pg_test]
+    fn test_detect_language_scores_one_language_script() {
+        let scores = detect_language_scores("Η γρήγορη καφέ αλεπού").unwrap().0;
+        assert_eq!(scores["language"], "ell");
+        assert_eq!(scores["confidence"], 1.0);
+    }
+
+    #[pg_test]
+    fn test_detect_language_scores_no_language() {
+        assert!(detect_language_scores("").is_none());
+    }
+}
@@ -2,17 +2,41 @@ use pgrx::prelude::*;
 
 mod anthropic;
 mod chunk;
+mod clean;
+mod document;
 mod docx;
+mod email;
+mod epub;
 mod errors;
 mod fireworks;
+mod html;
+mod ipynb;
 mod json_api;
+mod language;
+mod limits;
 mod markdown;
+mod odt;
 mod openai;
+mod package;
 mod pdf;
+mod pdf_layout;
+mod pptx;
+mod records;
+mod subtitles;
 mod voyageai;
+mod worker;
+mod xlsx;
+mod xpath;
 
 pg_module_magic!();
 
+#[pg_guard]
+pub extern "C-unwind" fn _PG_init() {
+    limits::define_gucs();
+    limits::install_panic_hook();
+    worker::register();
+}
+
 #[pg_schema]
 mod rag {
     use pgrx::prelude::*;
@@ -0,0 +1,459 @@
+// Document extraction runs on untrusted input inside the backend, so these settings put bounds on it
+
+use crate::errors::*;
+use pgrx::pg_sys::panic::ErrorReportWithLevel;
+use pgrx::prelude::*;
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::io::{Cursor, Read, Seek};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use zip::ZipArchive;
+
+pub static MAX_DOCUMENT_BYTES: GucSetting<i32> = GucSetting::<i32>::new(256 * 1024 * 1024);
+pub static MAX_PDF_PAGES: GucSetting<i32> = GucSetting::<i32>::new(5_000);
+pub static EXTRACTION_TIMEOUT: GucSetting<i32> = GucSetting::<i32>::new(0);
+pub static MAX_ZIP_ENTRIES: GucSetting<i32> = GucSetting::<i32>::new(10_000);
+pub static MAX_DECOMPRESSED_BYTES: GucSetting<i32> = GucSetting::<i32>::new(1024 * 1024 * 1024);
+
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub max_document_bytes: i32,
+    pub max_pdf_pages: i32,
+    pub max_zip_entries: i32,
+    pub max_decompressed_bytes: i32,
+}
+
+impl Limits {
+    // zero means no limit, so it's the least strict
+    pub fn stricter(self, other: Limits) -> Limits {
+        let stricter = |a: i32, b: i32| match (a, b) {
+            (0, limit) | (limit, 0) => limit,
+            (a, b) => a.min(b),
+        };
+        Limits {
+            max_document_bytes: stricter(self.max_document_bytes, other.max_document_bytes),
+            max_pdf_pages: stricter(self.max_pdf_pages, other.max_pdf_pages),
+            max_zip_entries: stricter(self.max_zip_entries, other.max_zip_entries),
+            max_decompressed_bytes: stricter(self.max_decompressed_bytes, other.max_decompressed_bytes),
+        }
+    }
+}
+
+thread_local! {
+    // set on extraction threads, where settings can't be read, to the limits that apply to the extraction
+    static EXTRACTION_LIMITS: Cell<Option<Limits>> = const { Cell::new(None) };
+    // set on the extraction threads that run_extraction starts, to tell them when they've been abandoned
+    static ABANDONED: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+// extraction threads still running in this process, including any abandoned after a timeout or cancellation
+static RUNNING_EXTRACTIONS: AtomicUsize = AtomicUsize::new(0);
+
+const EXTRACTION_THREAD_NAME: &str = "rag extraction";
+
+// how often to check for query cancellation while waiting for an extraction to finish
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub fn define_gucs() {
+    GucRegistry::define_int_guc(
+        c"rag.max_document_bytes",
+        c"Maximum size of a document from which text is extracted.",
+        c"Zero means no limit.",
+        &MAX_DOCUMENT_BYTES,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_BYTE,
+    );
+    GucRegistry::define_int_guc(
+        c"rag.max_pdf_pages",
+        c"Maximum number of pages in a PDF from which text is extracted.",
+        c"Zero means no limit.",
+        &MAX_PDF_PAGES,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"rag.extraction_timeout",
+        c"Maximum time to spend extracting text from a document.",
+        c"Zero means no limit, though statement_timeout still applies.",
+        &EXTRACTION_TIMEOUT,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_MS,
+    );
+    GucRegistry::define_int_guc(
+        c"rag.max_zip_entries",
+        c"Maximum number of files in a .zip file, or in a zip-based document such as .docx, that is read.",
+        c"Zero means no limit.",
+        &MAX_ZIP_ENTRIES,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"rag.max_decompressed_bytes",
+        c"Maximum total size of the files in a .zip file, or in a zip-based document such as .docx, once decompressed.",
+        c"Zero means no limit.",
+        &MAX_DECOMPRESSED_BYTES,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::UNIT_BYTE,
+    );
+}
+
+pub fn set_extraction_limits(limits: Limits) {
+    EXTRACTION_LIMITS.set(Some(limits));
+}
+
+pub fn on_extraction_thread() -> bool {
+    EXTRACTION_LIMITS.get().is_some()
+}
+
+// an extraction that can stop early, such as a request to the extraction worker, should stop once this is true
+pub fn extraction_abandoned() -> bool {
+    ABANDONED.with_borrow(|abandoned| {
+        abandoned
+            .as_ref()
+            .is_some_and(|abandoned| abandoned.load(Ordering::SeqCst))
+    })
+}
+
+pub fn session_limits() -> Limits {
+    EXTRACTION_LIMITS.get().unwrap_or_else(|| Limits {
+        max_document_bytes: MAX_DOCUMENT_BYTES.get(),
+        max_pdf_pages: MAX_PDF_PAGES.get(),
+        max_zip_entries: MAX_ZIP_ENTRIES.get(),
+        max_decompressed_bytes: MAX_DECOMPRESSED_BYTES.get(),
+    })
+}
+
+// zero means no limit, for this and the other settings
+pub fn max_bytes(limit: i32) -> u64 {
+    match limit {
+        0 => u64::MAX,
+        limit => limit as u64,
+    }
+}
+
+// limit breaches get their own SQLSTATE, 54000 (program_limit_exceeded), so they can be told apart from bad documents
+pub fn limit_exceeded(msg: &str) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+        format!("{ERR_PREFIX} {msg}")
+    );
+}
+
+pub fn check_document_size(document: &[u8]) {
+    check_document_size_with_limit(document, session_limits().max_document_bytes);
+}
+
+fn check_document_size_with_limit(document: &[u8], max_bytes: i32) {
+    if max_bytes > 0 && document.len() > max_bytes as usize {
+        limit_exceeded(&format!(
+            "Document is too large ({} bytes, but rag.max_document_bytes is {max_bytes})",
+            document.len()
+        ));
+    }
+}
+
+// A single part of a document, such as an image, is decompressed no further than the largest document that could be
+// read, since the libraries that would otherwise do it have no limit: decompressing one byte beyond max_bytes is enough
+// to tell that it's too large. description is what the part is, e.g. "Image in PDF".
+pub fn check_part_size(part: &[u8], description: &str, max_bytes: i32) {
+    if max_bytes > 0 && part.len() > max_bytes as usize {
+        limit_exceeded(&format!(
+            "{description} is too large when decompressed (rag.max_document_bytes is {max_bytes})"
+        ));
+    }
+}
+
+pub fn check_pdf_pages(page_count: usize) {
+    check_pdf_pages_with_limit(page_count, session_limits().max_pdf_pages);
+}
+
+fn check_pdf_pages_with_limit(page_count: usize, max_pages: i32) {
+    if max_pages > 0 && page_count > max_pages as usize {
+        limit_exceeded(&format!(
+            "PDF has too many pages ({page_count}, but rag.max_pdf_pages is {max_pages})"
+        ));
+    }
+}
+
+pub fn check_zip_entries(entry_count: usize, max_entries: i32) {
+    if max_entries > 0 && entry_count > max_entries as usize {
+        limit_exceeded(&format!(
+            ".zip file has too many entries ({entry_count}, but rag.max_zip_entries is {max_entries})"
+        ));
+    }
+}
+
+// An entry's declared size can't be trusted, so this decompresses each entry (reading no more than one byte beyond
+// what's allowed) and throws the result away. Returns None if the total is more than max_bytes. Entries that can't be
+// read are skipped: it's for whatever reads the file to report those.
+pub fn decompressed_zip_size(zip: &mut ZipArchive<impl Read + Seek>, max_bytes: u64) -> Option<u64> {
+    let mut total: u64 = 0;
+    for index in 0..zip.len() {
+        let Ok(entry) = zip.by_index(index) else {
+            continue;
+        };
+        let mut entry = entry.take((max_bytes - total).saturating_add(1));
+        let mut chunk = [0; 64 * 1024];
+        loop {
+            match entry.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => total += len as u64,
+            }
+        }
+        if total > max_bytes {
+            return None;
+        }
+    }
+    Some(total)
+}
+
+// Zip-based documents (.docx, .xlsx, .epub, etc.) are read by libraries that decompress their contents without any
+// limit, so this checks the contents first
+pub fn check_zip_document(document: &[u8]) {
+    let Ok(mut zip) = ZipArchive::new(Cursor::new(document)) else {
+        return; // the document's reader reports this
+    };
+    let limits = session_limits();
+    check_zip_entries(zip.len(), limits.max_zip_entries);
+    if decompressed_zip_size(&mut zip, max_bytes(limits.max_decompressed_bytes)).is_none() {
+        limit_exceeded(&format!(
+            "Document is too large when decompressed (rag.max_decompressed_bytes is {})",
+            limits.max_decompressed_bytes
+        ));
+    }
+}
+
+// Runs extract on its own thread, so that the backend can keep responding to query cancellation (which includes
+// statement_timeout) and enforce rag.extraction_timeout while it works. extract mustn't call into Postgres, except to
+// raise errors with error!, which are raised again here. A thread can't be killed, so on cancellation or timeout it's
+// left to finish, and the next extraction waits for it: a backend never has more than one runaway extraction. On an
+// extraction thread already (as when text_from_document calls text_from_pdf), extract is simply called.
+pub fn run_extraction<T: Send + 'static>(extract: impl FnOnce() -> T + Send + 'static) -> T {
+    if EXTRACTION_LIMITS.get().is_some() {
+        return extract();
+    }
+    run_extraction_with_timeout(extract, session_limits(), EXTRACTION_TIMEOUT.get())
+}
+
+// extraction threads are named, so that the panic hook can recognise them
+pub fn spawn_extraction_thread(f: impl FnOnce() + Send + 'static) -> std::io::Result<JoinHandle<()>> {
+    std::thread::Builder::new()
+        .name(EXTRACTION_THREAD_NAME.to_string())
+        .spawn(f)
+}
+
+// keeps count of the extraction threads that are running, even if a thread panics
+struct RunningExtraction;
+
+impl RunningExtraction {
+    fn start() -> Self {
+        RUNNING_EXTRACTIONS.fetch_add(1, Ordering::SeqCst);
+        RunningExtraction
+    }
+}
+
+impl Drop for RunningExtraction {
+    fn drop(&mut self) {
+        RUNNING_EXTRACTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// tells an extraction thread it's been abandoned when we stop waiting for it, whether or not it has finished
+struct AbandonOnDrop(Arc<AtomicBool>);
+
+impl Drop for AbandonOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+fn run_extraction_with_timeout<T: Send + 'static>(
+    extract: impl FnOnce() -> T + Send + 'static,
+    limits: Limits,
+    timeout_ms: i32,
+) -> T {
+    let deadline = (timeout_ms > 0).then(|| Instant::now() + Duration::from_millis(timeout_ms as u64));
+    let check_deadline = || {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            limit_exceeded(&format!(
+                "Extraction took too long (rag.extraction_timeout is {timeout_ms} ms)"
+            ));
+        }
+    };
+
+    while RUNNING_EXTRACTIONS.load(Ordering::SeqCst) > 0 {
+        check_for_interrupts!();
+        check_deadline();
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let running = RunningExtraction::start();
+    let abandoned = AbandonOnDrop(Arc::new(AtomicBool::new(false)));
+    let thread_abandoned = abandoned.0.clone();
+    spawn_extraction_thread(move || {
+        let _running = running;
+        EXTRACTION_LIMITS.set(Some(limits));
+        ABANDONED.set(Some(thread_abandoned));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(extract));
+        let _ = sender.send(result); // the receiver is gone if we were cancelled or timed out
+    })
+    .expect_or_pg_err("Couldn't start extraction thread");
+
+    loop {
+        check_for_interrupts!();
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(value)) => return value,
+            Ok(Err(payload)) => match payload.downcast::<ErrorReportWithLevel>() {
+                Ok(report) => std::panic::resume_unwind(report),
+                Err(payload) => error!("{ERR_PREFIX} Extraction failed: {}", panic_message(payload.as_ref())),
+            },
+            Err(RecvTimeoutError::Timeout) => check_deadline(),
+            Err(RecvTimeoutError::Disconnected) => error!("{ERR_PREFIX} Extraction thread exited unexpectedly"),
+        }
+    }
+}
+
+// Errors raised with error! are panics, and off the main thread the panic hook pgrx installs prints them to the server
+// log, though they're reported properly once raised again on the main thread. This keeps extraction threads quiet.
+pub fn install_panic_hook() {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let on_extraction_thread = std::thread::current().name() == Some(EXTRACTION_THREAD_NAME);
+        if !(on_extraction_thread && info.payload().is::<ErrorReportWithLevel>()) {
+            hook(info);
+        }
+    }));
+}
+
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown error")
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+
+    #[pg_test]
+    fn test_run_extraction() {
+        assert_eq!(run_extraction(|| 6 * 7), 42);
+    }
+
+    #[pg_test(error = "[rag] Extraction failed: oops")]
+    fn test_run_extraction_panic() {
+        run_extraction(|| -> i32 { panic!("oops") });
+    }
+
+    #[pg_test(error = "[rag] Document is too large (6 bytes, but rag.max_document_bytes is 5)")]
+    fn test_run_extraction_error() {
+        run_extraction(|| check_document_size_with_limit(b"123456", 5));
+    }
+
+    #[pg_test]
+    fn test_run_extraction_limits() {
+        let limits = Limits {
+            max_document_bytes: 1,
+            max_pdf_pages: 2,
+            max_zip_entries: 3,
+            max_decompressed_bytes: 4,
+        };
+        let pages = run_extraction_with_timeout(|| session_limits().max_pdf_pages, limits, 0);
+        assert_eq!(pages, 2);
+    }
+
+    #[pg_test]
+    fn test_run_extraction_nested() {
+        assert_eq!(run_extraction(|| run_extraction(|| 6 * 7)), 42);
+    }
+
+    #[pg_test]
+    fn test_extraction_abandoned() {
+        let (sender, receiver) = mpsc::channel();
+        let result = std::panic::catch_unwind(|| {
+            run_extraction_with_timeout(
+                move || {
+                    while !extraction_abandoned() {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    sender.send(()).unwrap();
+                },
+                session_limits(),
+                100,
+            )
+        });
+        assert!(result.is_err());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[pg_test]
+    fn test_limits_stricter() {
+        let limits = |max_document_bytes, max_pdf_pages| Limits {
+            max_document_bytes,
+            max_pdf_pages,
+            max_zip_entries: 0,
+            max_decompressed_bytes: 0,
+        };
+        let stricter = limits(100, 0).stricter(limits(0, 10));
+        assert_eq!((stricter.max_document_bytes, stricter.max_pdf_pages), (100, 10));
+        let stricter = limits(100, 5).stricter(limits(50, 10));
+        assert_eq!((stricter.max_document_bytes, stricter.max_pdf_pages), (50, 5));
+        assert_eq!(stricter.max_zip_entries, 0);
+    }
+
+    #[pg_test(error = "[rag] Extraction took too long (rag.extraction_timeout is 100 ms)")]
+    fn test_run_extraction_timeout() {
+        run_extraction_with_timeout(|| std::thread::sleep(Duration::from_secs(2)), session_limits(), 100);
+    }
+
+    #[pg_test]
+    fn test_check_document_size() {
+        check_document_size_with_limit(b"12345", 5);
+        check_document_size_with_limit(b"123456", 0);
+    }
+
+    #[pg_test(error = "[rag] Document is too large (6 bytes, but rag.max_document_bytes is 5)")]
+    fn test_check_document_size_too_large() {
+        check_document_size_with_limit(b"123456", 5);
+    }
+
+    #[pg_test]
+    fn test_decompressed_zip_size() {
+        let mut zip = ZipArchive::new(Cursor::new(include_bytes!("../test_res/test.zip"))).unwrap();
+        assert_eq!(decompressed_zip_size(&mut zip, u64::MAX), Some(1260));
+        assert_eq!(decompressed_zip_size(&mut zip, 1260), Some(1260));
+        assert_eq!(decompressed_zip_size(&mut zip, 1259), None);
+    }
+
+    #[pg_test(error = "[rag] .zip file has too many entries (7, but rag.max_zip_entries is 6)")]
+    fn test_check_zip_entries() {
+        check_zip_entries(7, 6);
+    }
+
+    #[pg_test(error = "[rag] PDF has too many pages (11, but rag.max_pdf_pages is 10)")]
+    fn test_check_pdf_pages() {
+        check_pdf_pages_with_limit(11, 10);
+    }
+}
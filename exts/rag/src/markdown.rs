@@ -1,15 +1,348 @@
+use crate::errors::*;
+use crate::html::{parse_html, serialize_html};
+use crate::limits::limit_exceeded;
+use htmd::options::{BulletListMarker, HeadingStyle, LinkStyle, Options};
+use htmd::*;
+use html5ever::local_name;
+use markup5ever_rcdom::{Handle, NodeData};
 use pgrx::prelude::*;
+use pulldown_cmark::{Event, MetadataBlockKind, Options as ParserOptions, Parser, Tag, TagEnd};
+use saphyr::{LoadableYamlNode, Scalar, Yaml};
+use serde::Deserialize;
+use std::collections::HashMap;
+use url::Url;
+
+const DEFAULT_SKIP_TAGS: [&str; 3] = ["head", "script", "style"];
+
+fn convert(converter: HtmlToMarkdown, document: &str) -> String {
+    converter
+        .convert(document)
+        .expect_or_pg_err("Error converting HTML to Markdown")
+}
+
+// shared with other extractors whose formats are HTML-based (e.g. EPUB)
+pub fn html_to_markdown(document: &str) -> String {
+    let converter = HtmlToMarkdown::builder().skip_tags(DEFAULT_SKIP_TAGS.to_vec()).build();
+    convert(converter, document)
+}
+
+// options
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum HeadingStyleOption {
+    #[default]
+    Atx,
+    Setext,
+}
+
+#[derive(Deserialize, Default)]
+enum BulletMarkerOption {
+    #[default]
+    #[serde(rename = "*")]
+    Asterisk,
+    #[serde(rename = "-")]
+    Dash,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum LinkStyleOption {
+    #[default]
+    Inline,
+    Reference,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MarkdownOptions {
+    skip_tags: Vec<String>,
+    heading_style: HeadingStyleOption,
+    bullet_marker: BulletMarkerOption,
+    link_style: LinkStyleOption,
+    keep_images: bool,
+    base_url: Option<String>,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        MarkdownOptions {
+            skip_tags: vec![],
+            heading_style: HeadingStyleOption::default(),
+            bullet_marker: BulletMarkerOption::default(),
+            link_style: LinkStyleOption::default(),
+            keep_images: true,
+            base_url: None,
+        }
+    }
+}
+
+// Drops the elements to be skipped and, given a base URL, makes the href of links and src of images absolute. Skipping
+// elements here rather than with htmd's skip_tags stops their links from turning up among reference-style links.
+fn prepare_node(node: &Handle, skip_tags: &[&str], base_url: Option<&Url>) {
+    let is_skipped = |child: &Handle| match &child.data {
+        NodeData::Element { name, .. } => skip_tags.contains(&name.local.as_ref()),
+        _ => false,
+    };
+    node.children.borrow_mut().retain(|child| !is_skipped(child));
+
+    if let (Some(base_url), NodeData::Element { name, attrs, .. }) = (base_url, &node.data) {
+        let url_attr = match name.local {
+            local_name!("a") => Some(local_name!("href")),
+            local_name!("img") => Some(local_name!("src")),
+            _ => None,
+        };
+        for attr in attrs.borrow_mut().iter_mut() {
+            if Some(&attr.name.local) == url_attr.as_ref() {
+                if let Ok(url) = base_url.join(attr.value.trim()) {
+                    attr.value = url.as_str().into();
+                }
+            }
+        }
+    }
+    for child in node.children.borrow().iter() {
+        prepare_node(child, skip_tags, base_url);
+    }
+}
+
+fn prepare_document(document: &str, skip_tags: &[&str], base_url: Option<&Url>) -> String {
+    let dom = parse_html(document);
+    prepare_node(&dom.document, skip_tags, base_url);
+    serialize_html(&dom.document)
+}
+
+fn html_to_markdown_with_options(document: &str, options: MarkdownOptions) -> String {
+    let mut skip_tags: Vec<&str> = DEFAULT_SKIP_TAGS.to_vec();
+    skip_tags.extend(options.skip_tags.iter().map(String::as_str));
+    if !options.keep_images {
+        skip_tags.push("img");
+    }
+    let base_url = options
+        .base_url
+        .map(|base_url| Url::parse(&base_url).expect_or_pg_err("Invalid base_url option"));
+    let converter = HtmlToMarkdown::builder()
+        .options(Options {
+            heading_style: match options.heading_style {
+                HeadingStyleOption::Atx => HeadingStyle::Atx,
+                HeadingStyleOption::Setext => HeadingStyle::Setex,
+            },
+            bullet_list_marker: match options.bullet_marker {
+                BulletMarkerOption::Asterisk => BulletListMarker::Asterisk,
+                BulletMarkerOption::Dash => BulletListMarker::Dash,
+            },
+            link_style: match options.link_style {
+                LinkStyleOption::Inline => LinkStyle::Inlined,
+                LinkStyleOption::Reference => LinkStyle::Referenced,
+            },
+            ..Options::default()
+        })
+        .build();
+    convert(converter, &prepare_document(document, &skip_tags, base_url.as_ref()))
+}
+
+// Markdown to plain text and front matter
+
+fn parse_markdown(document: &str) -> Parser<'_> {
+    let options = ParserOptions::ENABLE_TABLES
+        | ParserOptions::ENABLE_FOOTNOTES
+        | ParserOptions::ENABLE_STRIKETHROUGH
+        | ParserOptions::ENABLE_TASKLISTS
+        | ParserOptions::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | ParserOptions::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+    Parser::new_ext(document, options)
+}
+
+fn end_line(text: &mut String, newlines: usize) {
+    let trimmed_len = text.trim_end_matches([' ', '\t']).len();
+    text.truncate(trimmed_len);
+    if !text.is_empty() {
+        let existing = text.len() - text.trim_end_matches('\n').len();
+        text.push_str(&"\n".repeat(newlines.saturating_sub(existing)));
+    }
+}
+
+// Keeps the text of headings, paragraphs, lists, tables, code and link/image labels, dropping markup, URLs, raw HTML and
+// front matter. Blocks are separated by blank lines, list items and table rows by line breaks, table cells by tabs.
+fn markdown_to_text(document: &str) -> String {
+    let mut text = String::new();
+    let mut in_metadata = false;
+    let mut list_depth = 0;
+    for event in parse_markdown(document) {
+        match event {
+            Event::Start(Tag::MetadataBlock(_)) => in_metadata = true,
+            Event::End(TagEnd::MetadataBlock(_)) => in_metadata = false,
+            Event::Text(_) if in_metadata => {}
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => end_line(&mut text, 1),
+            Event::Start(Tag::Item) | Event::End(TagEnd::Item | TagEnd::TableHead | TagEnd::TableRow) => {
+                end_line(&mut text, 1)
+            }
+            Event::End(TagEnd::TableCell) => text.push('\t'),
+            Event::Start(Tag::List(_)) => {
+                end_line(&mut text, if list_depth > 0 { 1 } else { 2 });
+                list_depth += 1;
+            }
+            Event::End(TagEnd::List(_)) => {
+                list_depth -= 1;
+                end_line(&mut text, if list_depth > 0 { 1 } else { 2 });
+            }
+            Event::Start(
+                Tag::Paragraph
+                | Tag::Heading { .. }
+                | Tag::BlockQuote(_)
+                | Tag::CodeBlock(_)
+                | Tag::Table(_)
+                | Tag::FootnoteDefinition(_),
+            )
+            | Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::BlockQuote
+                | TagEnd::CodeBlock
+                | TagEnd::Table
+                | TagEnd::FootnoteDefinition,
+            )
+            | Event::Rule => end_line(&mut text, 2),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) => s.into(),
+        toml::Value::Integer(i) => i.into(),
+        toml::Value::Float(f) => f.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(dt) => dt.to_string().into(),
+        toml::Value::Array(values) => values.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect(),
+    }
+}
+
+// saphyr copies an anchored node wherever it's aliased, so YAML that nests aliases (a "billion laughs" attack) can
+// expand without any limit: this counts the nodes it would expand to before it's loaded
+const MAX_YAML_NODES: usize = 100_000;
+
+fn check_yaml_expansion(source: &str) {
+    let mut anchored_nodes = HashMap::new(); // anchor id -> nodes in the anchored node, expanded
+    let mut open: Vec<(usize, usize)> = vec![(0, 0)]; // (anchor id, nodes so far) for each open collection, under the document as a whole
+    for event in saphyr_parser::Parser::new_from_str(source) {
+        let Ok((event, _)) = event else {
+            return; // loading reports the error
+        };
+        let (anchor_id, nodes) = match event {
+            saphyr_parser::Event::SequenceStart(anchor_id, _) | saphyr_parser::Event::MappingStart(anchor_id, _) => {
+                open.push((anchor_id, 1));
+                continue;
+            }
+            saphyr_parser::Event::SequenceEnd | saphyr_parser::Event::MappingEnd => match open.pop() {
+                Some(collection) => collection,
+                None => return,
+            },
+            saphyr_parser::Event::Scalar(_, _, anchor_id, _) => (anchor_id, 1),
+            saphyr_parser::Event::Alias(anchor_id) => (0, anchored_nodes.get(&anchor_id).copied().unwrap_or(1)),
+            _ => continue,
+        };
+        if anchor_id > 0 {
+            anchored_nodes.insert(anchor_id, nodes);
+        }
+        let Some((_, parent_nodes)) = open.last_mut() else {
+            return;
+        };
+        *parent_nodes = nodes.saturating_add(*parent_nodes);
+        if *parent_nodes > MAX_YAML_NODES {
+            limit_exceeded(&format!(
+                "YAML front matter is too large once its aliases are expanded (more than {MAX_YAML_NODES} values)"
+            ));
+        }
+    }
+}
+
+// keys that aren't strings (numbers, say) become their JSON representation
+fn yaml_to_json(yaml: Yaml) -> serde_json::Value {
+    match yaml {
+        Yaml::Value(Scalar::Null) | Yaml::Alias(_) | Yaml::BadValue => serde_json::Value::Null,
+        Yaml::Value(Scalar::Boolean(b)) => b.into(),
+        Yaml::Value(Scalar::Integer(i)) => i.into(),
+        Yaml::Value(Scalar::FloatingPoint(f)) => f.into_inner().into(),
+        Yaml::Value(Scalar::String(s)) | Yaml::Representation(s, _, _) => s.into_owned().into(),
+        Yaml::Sequence(values) => values.into_iter().map(yaml_to_json).collect(),
+        Yaml::Mapping(mapping) => mapping
+            .into_iter()
+            .map(|(key, value)| {
+                let key = match yaml_to_json(key) {
+                    serde_json::Value::String(key) => key,
+                    key => key.to_string(),
+                };
+                (key, yaml_to_json(value))
+            })
+            .collect(),
+        Yaml::Tagged(_, value) => yaml_to_json(*value),
+    }
+}
+
+fn yaml_front_matter(source: &str) -> serde_json::Value {
+    check_yaml_expansion(source);
+    let documents = Yaml::load_from_str(source).expect_or_pg_err("Invalid YAML front matter");
+    documents
+        .into_iter()
+        .next()
+        .map_or(serde_json::Value::Null, yaml_to_json)
+}
+
+// YAML front matter is delimited by --- lines (the closing one may also be ...) and TOML front matter by +++ lines
+fn markdown_front_matter(document: &str) -> Option<serde_json::Value> {
+    let mut kind = None;
+    let mut source = String::new();
+    for event in parse_markdown(document) {
+        match event {
+            Event::Start(Tag::MetadataBlock(k)) => kind = Some(k),
+            Event::Text(s) if kind.is_some() => source.push_str(&s),
+            Event::End(TagEnd::MetadataBlock(_)) => break,
+            _ if kind.is_none() => return None, // front matter can only open a document
+            _ => {}
+        }
+    }
+    let value = match kind? {
+        MetadataBlockKind::YamlStyle => yaml_front_matter(&source),
+        MetadataBlockKind::PlusesStyle => toml_to_json(toml::Value::Table(
+            toml::from_str(&source).expect_or_pg_err("Invalid TOML front matter"),
+        )),
+    };
+    Some(match value {
+        serde_json::Value::Null => serde_json::json!({}),
+        value => value,
+    })
+}
 
 #[pg_schema]
 mod rag {
     use super::super::errors::*;
+    use super::{html_to_markdown, html_to_markdown_with_options, markdown_front_matter, markdown_to_text};
     use pgrx::prelude::*;
-    use htmd::*;
 
     #[pg_extern(immutable, strict)]
     pub fn markdown_from_html(document: &str) -> String {
-        let converter = HtmlToMarkdown::builder().skip_tags(vec!["head", "script", "style"]).build();
-        converter.convert(document).expect_or_pg_err("Error converting HTML to Markdown")
+        html_to_markdown(document)
+    }
+
+    #[pg_extern(immutable, strict, name = "markdown_from_html")]
+    pub fn markdown_from_html_with_options(document: &str, options: pgrx::JsonB) -> String {
+        let options = serde_json::from_value(options.0).expect_or_pg_err("Invalid Markdown options");
+        html_to_markdown_with_options(document, options)
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn text_from_markdown(document: &str) -> String {
+        markdown_to_text(document)
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn frontmatter_from_markdown(document: &str) -> Option<pgrx::JsonB> {
+        markdown_front_matter(document).map(pgrx::JsonB)
     }
 }
 
@@ -18,6 +351,7 @@ mod rag {
 mod tests {
     use super::rag::*;
     use pgrx::prelude::*;
+    use serde_json::json;
 
     #[pg_test]
     fn test_markdown_from_html() {
@@ -26,4 +360,145 @@ mod tests {
             "# Heading\n\nPara"
         )
     }
+
+    const PAGE: &str = "<html><body><nav><a href='/'>Home</a></nav><h1>Heading</h1><ul><li>One</li></ul>\
+        <p>A <a href='docs/intro.html'>link</a> and an <img src='/img/a.png' alt='image'></p>\
+        <footer>Copyright</footer></body></html>";
+
+    #[pg_test]
+    fn test_markdown_from_html_with_default_options() {
+        assert_eq!(
+            markdown_from_html_with_options(PAGE, pgrx::JsonB(json!({}))),
+            markdown_from_html(PAGE)
+        )
+    }
+
+    #[pg_test]
+    fn test_markdown_from_html_with_options() {
+        let options = json!({
+            "skip_tags": ["nav", "footer"],
+            "heading_style": "setext",
+            "bullet_marker": "-",
+            "link_style": "reference",
+            "keep_images": false,
+            "base_url": "https://example.com/site/",
+        });
+        assert_eq!(
+            markdown_from_html_with_options(PAGE, pgrx::JsonB(options)),
+            "Heading\n=======\n\n-   One\n\nA [link][1] and an\n\n[1]: https://example.com/site/docs/intro.html"
+        )
+    }
+
+    #[pg_test]
+    fn test_markdown_from_html_with_base_url() {
+        assert_eq!(
+            markdown_from_html_with_options(
+                "<p><a href='#top'>Top</a> <img src='a.png' alt='A'></p>",
+                pgrx::JsonB(json!({"base_url": "https://example.com/page"}))
+            ),
+            "[Top](https://example.com/page#top) ![A](https://example.com/a.png)"
+        )
+    }
+
+    #[pg_test(
+        error = "[rag] Invalid Markdown options: unknown field `skip`, expected one of `skip_tags`, `heading_style`, `bullet_marker`, `link_style`, `keep_images`, `base_url`"
+    )]
+    fn test_markdown_from_html_with_unknown_option() {
+        markdown_from_html_with_options(PAGE, pgrx::JsonB(json!({"skip": ["nav"]})));
+    }
+
+    #[pg_test(error = "[rag] Invalid base_url option: relative URL without a base")]
+    fn test_markdown_from_html_with_relative_base_url() {
+        markdown_from_html_with_options(PAGE, pgrx::JsonB(json!({"base_url": "/site/"})));
+    }
+
+    const NOTE: &str = "---
+title: Release notes
+tags: [rag, postgres]
+draft: false
+---
+
+# Release *notes*
+
+Some **bold** text with a [link](https://example.com) and `code`,
+wrapped over two lines.
+
+- One
+  - One point five
+- Two
+
+| Name | Value |
+|------|-------|
+| a    | 1     |
+
+```sql
+select 1;
+```
+
+> Quoted ![image](a.png)
+";
+
+    #[pg_test]
+    fn test_text_from_markdown() {
+        assert_eq!(
+            text_from_markdown(NOTE),
+            "Release notes\n\n\
+            Some bold text with a link and code, wrapped over two lines.\n\n\
+            One\nOne point five\nTwo\n\n\
+            Name\tValue\na\t1\n\n\
+            select 1;\n\n\
+            Quoted image"
+        )
+    }
+
+    #[pg_test]
+    fn test_frontmatter_from_markdown_yaml() {
+        assert_eq!(
+            frontmatter_from_markdown(NOTE).unwrap().0,
+            json!({"title": "Release notes", "tags": ["rag", "postgres"], "draft": false})
+        )
+    }
+
+    #[pg_test]
+    fn test_frontmatter_from_markdown_toml() {
+        assert_eq!(
+            frontmatter_from_markdown("+++\ntitle = \"Notes\"\ndate = 2024-05-01\n[extra]\nweight = 2\n+++\n\nBody")
+                .unwrap()
+                .0,
+            json!({"title": "Notes", "date": "2024-05-01", "extra": {"weight": 2}})
+        )
+    }
+
+    #[pg_test]
+    fn test_frontmatter_from_markdown_missing() {
+        assert!(frontmatter_from_markdown("# Title\n\n---\nnot: front matter\n---\n").is_none())
+    }
+
+    #[pg_test(
+        error = "[rag] Invalid YAML front matter: while parsing a node, did not find expected node content at byte 8 line 2 column 1"
+    )]
+    fn test_frontmatter_from_markdown_invalid() {
+        frontmatter_from_markdown("---\ntags: [\n---\n");
+    }
+
+    #[pg_test]
+    fn test_frontmatter_from_markdown_yaml_aliases_and_keys() {
+        assert_eq!(
+            frontmatter_from_markdown("---\nbase: &base {weight: 1.5}\npage: *base\n2024: true\n---\n")
+                .unwrap()
+                .0,
+            json!({"base": {"weight": 1.5}, "page": {"weight": 1.5}, "2024": true})
+        )
+    }
+
+    // each level of aliases multiplies the nodes by ten: a billion at the ninth
+    #[pg_test(error = "[rag] YAML front matter is too large once its aliases are expanded (more than 100000 values)")]
+    fn test_frontmatter_from_markdown_yaml_alias_expansion() {
+        let mut yaml = "---\na0: &a0 [x, x, x, x, x, x, x, x, x, x]\n".to_string();
+        for level in 1..10 {
+            let aliases = vec![format!("*a{}", level - 1); 10].join(", ");
+            yaml.push_str(&format!("a{level}: &a{level} [{aliases}]\n"));
+        }
+        frontmatter_from_markdown(&format!("{yaml}---\n"));
+    }
 }
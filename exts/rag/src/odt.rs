@@ -0,0 +1,95 @@
+use pgrx::prelude::*;
+
+#[pg_schema]
+pub(crate) mod rag {
+    use super::super::errors::*;
+    use super::super::package::*;
+    use super::super::worker;
+    use pgrx::prelude::*;
+    use roxmltree::Node;
+
+    const NS_OFFICE: &str = "urn:oasis:names:tc:opendocument:xmlns:office:1.0";
+    const NS_TEXT: &str = "urn:oasis:names:tc:opendocument:xmlns:text:1.0";
+
+    // notes, comments and deleted text (in tracked changes) aren't part of the running text
+    fn is_excluded(node: Node) -> bool {
+        node.has_tag_name((NS_TEXT, "note"))
+            || node.has_tag_name((NS_TEXT, "tracked-changes"))
+            || node.has_tag_name((NS_OFFICE, "annotation"))
+    }
+
+    fn is_paragraph(node: Node) -> bool {
+        node.has_tag_name((NS_TEXT, "p")) || node.has_tag_name((NS_TEXT, "h"))
+    }
+
+    fn inline_text(node: Node, text: &mut String) {
+        for child in node.children() {
+            if child.is_text() {
+                text.push_str(child.text().unwrap_or(""));
+            } else if child.has_tag_name((NS_TEXT, "s")) {
+                let count = child
+                    .attribute((NS_TEXT, "c"))
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or(1);
+                text.push_str(&" ".repeat(count));
+            } else if child.has_tag_name((NS_TEXT, "tab")) {
+                text.push('\t');
+            } else if child.has_tag_name((NS_TEXT, "line-break")) {
+                text.push('\n');
+            } else if child.is_element() && !is_excluded(child) && !is_paragraph(child) {
+                // paragraphs nested inside others (e.g. in text boxes) are output separately
+                inline_text(child, text);
+            }
+        }
+    }
+
+    pub(crate) fn odt_text(document: Vec<u8>) -> String {
+        let mut odt = Package::new(document, ".odt file");
+        let content_path = "content.xml";
+        let content_xml = odt
+            .read(content_path)
+            .unwrap_or_pg_err("Couldn't find content in .odt file");
+        let content = odt.parse(&content_xml, content_path);
+        let body = content
+            .descendants()
+            .find(|node| node.has_tag_name((NS_OFFICE, "text")))
+            .unwrap_or_pg_err("Couldn't find text in .odt file");
+
+        body.descendants()
+            .filter(|node| is_paragraph(*node) && !node.ancestors().any(is_excluded))
+            .map(|paragraph| {
+                let mut text = String::new();
+                inline_text(paragraph, &mut text);
+                text
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn text_from_odt(document: Vec<u8>) -> String {
+        worker::extract_value("text_from_odt", document, &[])
+    }
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_text_from_odt() {
+        assert_eq!(
+            text_from_odt(include_bytes!("../test_res/test.odt").to_vec()),
+            "Test .odt document\nSome styled text,  spaced\ttabbed\nand broken.\nOne\nTwo\nA1\nB1\n\nLast link."
+        );
+    }
+
+    #[pg_test(error = "[rag] Couldn't find content in .odt file")]
+    fn test_text_from_not_odt() {
+        text_from_odt(include_bytes!("../test_res/test.docx").to_vec());
+    }
+}
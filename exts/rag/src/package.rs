@@ -0,0 +1,126 @@
+// Zip-based document formats (.pptx, .epub, .odt, etc.) are packages of XML files that refer to one another by path
+
+use crate::errors::*;
+use crate::limits::{check_document_size, check_part_size, check_zip_document, max_bytes, session_limits};
+use roxmltree::Document;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+pub struct Package {
+    zip: ZipArchive<Cursor<Vec<u8>>>,
+    description: &'static str,
+}
+
+impl Package {
+    // description is used in error messages, e.g. ".pptx file"
+    pub fn new(document: Vec<u8>, description: &'static str) -> Self {
+        check_document_size(&document);
+        check_zip_document(&document);
+        let zip = ZipArchive::new(Cursor::new(document)).expect_or_pg_err(&format!("Couldn't read {description}"));
+        Package { zip, description }
+    }
+
+    // returns None if the package doesn't contain the file
+    pub fn read(&mut self, path: &str) -> Option<String> {
+        let bytes = self.read_bytes(path)?;
+        let text = String::from_utf8(bytes).expect_or_pg_err(&format!("Couldn't read {path} in {}", self.description));
+        Some(text)
+    }
+
+    // like read, for binary files such as images
+    pub fn read_bytes(&mut self, path: &str) -> Option<Vec<u8>> {
+        self.read_bytes_with_limit(path, session_limits().max_document_bytes)
+    }
+
+    fn read_bytes_with_limit(&mut self, path: &str, max_document_bytes: i32) -> Option<Vec<u8>> {
+        let file = self.zip.by_name(path).ok()?;
+        let mut bytes = Vec::new();
+        file.take(max_bytes(max_document_bytes).saturating_add(1))
+            .read_to_end(&mut bytes)
+            .expect_or_pg_err(&format!("Couldn't read {path} in {}", self.description));
+        check_part_size(&bytes, &format!("{path} in {}", self.description), max_document_bytes);
+        Some(bytes)
+    }
+
+    pub fn parse<'a>(&self, xml: &'a str, path: &str) -> Document<'a> {
+        Document::parse(xml).expect_or_pg_err(&format!("Couldn't parse {path} in {}", self.description))
+    }
+
+    // maps relationship id -> (type, resolved target path)
+    pub fn relationships(&mut self, part: &str) -> HashMap<String, (String, String)> {
+        let (dir, file) = part.rsplit_once('/').unwrap_or(("", part));
+        let rels_path = format!("{dir}/_rels/{file}.rels");
+        let Some(xml) = self.read(&rels_path) else {
+            return HashMap::new();
+        };
+        let rels = self.parse(&xml, &rels_path);
+        rels.descendants()
+            .filter(|node| node.has_tag_name("Relationship"))
+            .filter_map(|node| {
+                Some((
+                    node.attribute("Id")?.to_string(),
+                    (
+                        node.attribute("Type")?.to_string(),
+                        resolve_path(part, node.attribute("Target")?),
+                    ),
+                ))
+            })
+            .collect()
+    }
+}
+
+// references are relative to the directory of the file they appear in, unless they start with '/'
+pub fn resolve_path(file: &str, reference: &str) -> String {
+    if let Some(absolute) = reference.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut segments: Vec<&str> = file.split('/').collect();
+    segments.pop();
+    for segment in reference.split('/') {
+        match segment {
+            ".." => {
+                segments.pop();
+            }
+            "." | "" => (),
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+    use pgrx::prelude::*;
+    use std::io::Write;
+
+    fn package_with_part(part: &[u8]) -> Package {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("media/image.bin", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(part).unwrap();
+        Package::new(zip.finish().unwrap().into_inner(), ".test file")
+    }
+
+    #[pg_test]
+    fn test_read_bytes_with_limit() {
+        let mut package = package_with_part(&[0; 1000]);
+        assert_eq!(
+            package.read_bytes_with_limit("media/image.bin", 1000),
+            Some(vec![0; 1000])
+        );
+        assert_eq!(package.read_bytes_with_limit("media/missing.bin", 1000), None);
+    }
+
+    // the part compresses to far less than the limit
+    #[pg_test(
+        error = "[rag] media/image.bin in .test file is too large when decompressed (rag.max_document_bytes is 999)"
+    )]
+    fn test_read_bytes_too_large() {
+        package_with_part(&[0; 1000]).read_bytes_with_limit("media/image.bin", 999);
+    }
+}
@@ -1,15 +1,540 @@
 use pgrx::prelude::*;
 
 #[pg_schema]
-mod rag {
+pub(crate) mod rag {
+    use super::super::document::ImageRow;
     use super::super::errors::*;
+    use super::super::limits::{
+        check_document_size, check_part_size, check_pdf_pages, max_bytes, panic_message, session_limits,
+    };
+    use super::super::pdf_layout::LayoutOutput;
+    use super::super::worker;
+    use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+    use lopdf::{Dictionary, Object, ObjectId, Stream};
+    use pgrx::pg_sys::panic::ErrorReportWithLevel;
     use pgrx::prelude::*;
+    use serde_json::{json, Map, Value};
+    use std::collections::HashSet;
+    use std::io::{Read, Write};
     use unicode_normalization::UnicodeNormalization;
 
+    // loads a PDF and, if it's encrypted, decrypts it: the empty password works for PDFs that have only an owner
+    // password (restricting printing, editing, etc.)
+    fn load_pdf(document: &[u8], password: Option<&str>, error_message: &str) -> lopdf::Document {
+        check_document_size(document);
+        let mut doc = lopdf::Document::load_mem(document)
+            .map_err(pdf_extract::OutputError::from)
+            .expect_or_pg_err(error_message);
+        if doc.is_encrypted() {
+            let skipped_ids: Vec<ObjectId> = [&b"Encrypt"[..], b"Info"]
+                .into_iter()
+                .filter_map(|key| doc.trailer.get(key).and_then(Object::as_reference).ok())
+                .collect();
+            let key = lopdf::encryption::get_encryption_key(&doc, password.unwrap_or(""), true);
+            match doc.decrypt(password.unwrap_or("")) {
+                Ok(()) => {
+                    if let Ok(key) = key {
+                        decrypt_nested_strings(&mut doc, &key, &skipped_ids);
+                    }
+                }
+                Err(lopdf::Error::Decryption(lopdf::encryption::DecryptionError::IncorrectPassword)) => {
+                    match password {
+                        None => error!("{ERR_PREFIX} PDF is encrypted: a password is required"),
+                        Some(_) => error!("{ERR_PREFIX} Wrong password for encrypted PDF"),
+                    }
+                }
+                Err(err) => error!("{ERR_PREFIX} Error decrypting PDF: {err}"),
+            }
+        }
+        check_pdf_pages(doc.get_pages().len());
+        doc
+    }
+
+    fn decrypt_strings(object: &mut Object, key: &[u8], id: ObjectId) {
+        match object {
+            Object::Array(items) => items.iter_mut().for_each(|item| decrypt_strings(item, key, id)),
+            Object::Dictionary(dict) => dict.iter_mut().for_each(|(_, value)| decrypt_strings(value, key, id)),
+            Object::Stream(stream) => stream.dict.iter_mut().for_each(|(_, value)| decrypt_strings(value, key, id)),
+            Object::String(..) => {
+                if let Ok(decrypted) = lopdf::encryption::decrypt_object(key, id, object) {
+                    *object = Object::String(decrypted, lopdf::StringFormat::Literal);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // lopdf decrypts strings that are objects in their own right, and those in the document info dictionary, but not
+    // strings inside other dictionaries and arrays, such as outline titles and link URIs. Each is encrypted with the
+    // key of the object it's in.
+    fn decrypt_nested_strings(doc: &mut lopdf::Document, key: &[u8], skipped_ids: &[ObjectId]) {
+        for (&id, object) in doc.objects.iter_mut() {
+            if !skipped_ids.contains(&id) && !matches!(object, Object::String(..)) {
+                decrypt_strings(object, key, id);
+            }
+        }
+    }
+
+    fn text_from_loaded_pdf(doc: lopdf::Document) -> String {
+        let mut raw = String::new();
+        pdf_extract::output_doc(&doc, &mut pdf_extract::PlainTextOutput::new(&mut raw))
+            .expect_or_pg_err("Error extracting text from PDF");
+        raw.nfkc().collect()  // PDFs often contain ligatures: separate these out into individual characters
+    }
+
+    pub(crate) fn pdf_text(document: &[u8], password: Option<&str>) -> String {
+        text_from_loaded_pdf(load_pdf(document, password, "Error extracting text from PDF"))
+    }
+
     #[pg_extern(immutable, strict)]
     pub fn text_from_pdf(document: &[u8]) -> String {
-        let raw = pdf_extract::extract_text_from_mem(&document).expect_or_pg_err("Error extracting text from PDF");
-        raw.nfkc().collect()  // PDFs often contain ligatures: separate these out into individual characters
+        worker::extract_value("text_from_pdf", document.to_vec(), &[])
+    }
+
+    #[pg_extern(immutable, strict, name = "text_from_pdf")]
+    pub fn text_from_pdf_with_password(document: &[u8], password: &str) -> String {
+        worker::extract_value("text_from_pdf", document.to_vec(), &[password])
+    }
+
+    pub(crate) fn pdf_pages(document: &[u8]) -> Vec<(i32, String)> {
+        let doc = load_pdf(document, None, "Error extracting text from PDF");
+        let mut pages = Vec::new();
+        for page_number in doc.get_pages().into_keys() {
+            let mut raw = String::new();
+            let mut output = pdf_extract::PlainTextOutput::new(&mut raw);
+            // pdf_extract panics on most malformed pages, rather than returning an error
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                pdf_extract::output_doc_page(&doc, &mut output, page_number)
+            }));
+            let message = match result {
+                Ok(Ok(())) => None,
+                Ok(Err(err)) => Some(err.to_string()),
+                Err(payload) if payload.is::<ErrorReportWithLevel>() => std::panic::resume_unwind(payload),
+                Err(payload) => Some(panic_message(payload.as_ref()).to_string()),
+            };
+            if let Some(message) = message {
+                error!("{ERR_PREFIX} Error extracting text from PDF page {page_number}: {message}");
+            }
+            pages.push((page_number as i32, raw.nfkc().collect()));
+        }
+        pages
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn pages_from_pdf(document: &[u8]) -> TableIterator<'static, (name!(page_number, i32), name!(text, String))> {
+        TableIterator::new(worker::extract("pages_from_pdf", document.to_vec(), &[]))
+    }
+
+    pub(crate) fn pdf_blocks(document: &[u8]) -> Vec<(i32, i32, String, Vec<f64>, String)> {
+        let doc = load_pdf(document, None, "Error extracting text from PDF");
+        let mut output = LayoutOutput::default();
+        pdf_extract::output_doc(&doc, &mut output).expect_or_pg_err("Error extracting text from PDF");
+        let mut rows = Vec::new();
+        for (page, blocks) in output.into_blocks() {
+            for (i, block) in blocks.into_iter().enumerate() {
+                let text = block.text.nfkc().collect();
+                rows.push((
+                    page as i32,
+                    (i + 1) as i32,
+                    block.kind.to_string(),
+                    block.bbox.to_vec(),
+                    text,
+                ));
+            }
+        }
+        rows
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn blocks_from_pdf(
+        document: &[u8],
+    ) -> TableIterator<
+        'static,
+        (
+            name!(page, i32),
+            name!(block_index, i32),
+            name!(kind, String),
+            name!(bbox, Vec<f64>),
+            name!(text, String),
+        ),
+    > {
+        TableIterator::new(worker::extract("blocks_from_pdf", document.to_vec(), &[]))
+    }
+
+    // images
+
+    // how an image's samples map onto a PNG colour type: palettes are RGB triples
+    enum PngColor {
+        Gray,
+        Rgb,
+        Palette(Vec<u8>),
+    }
+
+    // lopdf refuses to decode image streams, since it can't handle image-specific filters such as DCTDecode, but the
+    // general-purpose filters (FlateDecode etc.) work as they do for any other stream
+    fn decoded_content(stream: &Stream) -> Option<Vec<u8>> {
+        decoded_content_with_limit(stream, session_limits().max_document_bytes)
+    }
+
+    // lopdf decompresses streams without any limit, so image data is decoded here instead, and raises an error once
+    // it's larger than a document may be. Returns None for streams that can't be decoded.
+    pub(super) fn decoded_content_with_limit(stream: &Stream, max_document_bytes: i32) -> Option<Vec<u8>> {
+        let Ok(filters) = stream.filters() else {
+            return stream.dict.get(b"Filter").is_err().then(|| stream.content.clone());
+        };
+        let max_bytes = max_bytes(max_document_bytes);
+        let params = stream.dict.get(b"DecodeParms").and_then(Object::as_dict).ok();
+        let mut content = stream.content.clone();
+        for filter in filters {
+            content = match filter.as_str() {
+                "FlateDecode" => unpredicted(read_limited(ZlibDecoder::new(content.as_slice()), max_bytes), params)?,
+                "LZWDecode" => unpredicted(lzw_decoded(&content, params, max_bytes), params)?,
+                // the output is at most four times the input
+                "ASCII85Decode" => {
+                    let dict = Dictionary::from_iter([("Filter", Object::Name(b"ASCII85Decode".to_vec()))]);
+                    Stream::new(dict, content).decompressed_content().ok()?
+                }
+                _ => return None,
+            };
+            check_part_size(&content, "Image in PDF", max_document_bytes);
+        }
+        Some(content)
+    }
+
+    // reads no more than one byte beyond max_bytes, keeping whatever was decoded before any error, as lopdf does
+    fn read_limited(reader: impl Read, max_bytes: u64) -> Vec<u8> {
+        let mut output = Vec::new();
+        let _ = reader.take(max_bytes.saturating_add(1)).read_to_end(&mut output);
+        output
+    }
+
+    fn lzw_decoded(content: &[u8], params: Option<&Dictionary>, max_bytes: u64) -> Vec<u8> {
+        let early_change = params.and_then(|params| params.get(b"EarlyChange").and_then(Object::as_i64).ok());
+        let mut decoder = match early_change {
+            Some(0) => weezl::decode::Decoder::new(weezl::BitOrder::Msb, 8),
+            _ => weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8),
+        };
+        let (mut input, mut output, mut buffer) = (content, Vec::new(), vec![0; 64 * 1024]);
+        while output.len() as u64 <= max_bytes {
+            let result = decoder.decode_bytes(input, &mut buffer);
+            input = &input[result.consumed_in..];
+            output.extend_from_slice(&buffer[..result.consumed_out]);
+            if !matches!(result.status, Ok(weezl::LzwStatus::Ok)) {
+                break;
+            }
+        }
+        output
+    }
+
+    // undoes PNG prediction, which is all lopdf supports too: no row can be longer than the data
+    fn unpredicted(data: Vec<u8>, params: Option<&Dictionary>) -> Option<Vec<u8>> {
+        let Some(params) = params else {
+            return Some(data);
+        };
+        let param = |key: &[u8], default: i64| params.get(key).and_then(Object::as_i64).unwrap_or(default);
+        if !(10..=15).contains(&param(b"Predictor", 1)) {
+            return Some(data);
+        }
+        let columns = usize::try_from(param(b"Columns", 1).max(1)).ok()?;
+        let colors = usize::try_from(param(b"Colors", 1).max(1)).ok()?;
+        let bits = usize::try_from(param(b"BitsPerComponent", 8).max(8)).ok()?;
+        let bytes_per_pixel = colors.checked_mul(bits)? / 8;
+        if bytes_per_pixel.checked_mul(columns)? > data.len() {
+            return None;
+        }
+        lopdf::filters::png::decode_frame(&data, bytes_per_pixel, columns).ok()
+    }
+
+    // returns None for colour spaces PNG can't represent, such as DeviceCMYK
+    fn png_color(doc: &lopdf::Document, color_space: &Object) -> Option<PngColor> {
+        let (_, color_space) = doc.dereference(color_space).ok()?;
+        if let Ok(name) = color_space.as_name() {
+            return match name {
+                b"DeviceGray" | b"CalGray" => Some(PngColor::Gray),
+                b"DeviceRGB" | b"CalRGB" => Some(PngColor::Rgb),
+                _ => None,
+            };
+        }
+        let array = color_space.as_array().ok()?;
+        match array.first()?.as_name().ok()? {
+            b"CalGray" => Some(PngColor::Gray),
+            b"CalRGB" => Some(PngColor::Rgb),
+            b"ICCBased" => {
+                let (_, profile) = doc.dereference(array.get(1)?).ok()?;
+                match profile.as_stream().ok()?.dict.get(b"N").and_then(Object::as_i64).ok()? {
+                    1 => Some(PngColor::Gray),
+                    3 => Some(PngColor::Rgb),
+                    _ => None,
+                }
+            }
+            b"Indexed" => {
+                let lookup = match doc.dereference(array.get(3)?).ok()?.1 {
+                    Object::String(bytes, _) => bytes.clone(),
+                    Object::Stream(stream) => decoded_content(stream)?,
+                    _ => return None,
+                };
+                let mut palette = match png_color(doc, array.get(1)?)? {
+                    PngColor::Gray => lookup.iter().flat_map(|&gray| [gray; 3]).collect(),
+                    PngColor::Rgb => lookup,
+                    PngColor::Palette(_) => return None,
+                };
+                palette.truncate((palette.len() / 3).min(256) * 3);
+                (!palette.is_empty()).then_some(PngColor::Palette(palette))
+            }
+            _ => None,
+        }
+    }
+
+    fn push_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        png.extend((data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend(kind);
+        png.extend(data);
+        let mut crc = flate2::Crc::new();
+        crc.update(&png[start..]);
+        png.extend(crc.sum().to_be_bytes());
+    }
+
+    // PDF image samples are packed into rows just as PNG's are, so each row only needs a filter type byte (0: none)
+    fn png_from_samples(width: usize, height: usize, bits: u8, color: &PngColor, samples: &[u8]) -> Option<Vec<u8>> {
+        let (color_type, channels, depths): (u8, usize, &[u8]) = match color {
+            PngColor::Gray => (0, 1, &[1, 2, 4, 8, 16]),
+            PngColor::Rgb => (2, 3, &[8, 16]),
+            PngColor::Palette(_) => (3, 1, &[1, 2, 4, 8]),
+        };
+        if !depths.contains(&bits) {
+            return None;
+        }
+        let row_len = (width * channels * bits as usize).div_ceil(8);
+        let rows = samples.get(..row_len.checked_mul(height)?)?;
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        for row in rows.chunks(row_len) {
+            encoder.write_all(&[0]).ok()?;
+            encoder.write_all(row).ok()?;
+        }
+        let mut header = Vec::with_capacity(13);
+        header.extend((width as u32).to_be_bytes());
+        header.extend((height as u32).to_be_bytes());
+        header.extend([bits, color_type, 0, 0, 0]); // compression, filter and interlace methods
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        push_png_chunk(&mut png, b"IHDR", &header);
+        if let PngColor::Palette(palette) = color {
+            push_png_chunk(&mut png, b"PLTE", palette);
+        }
+        push_png_chunk(&mut png, b"IDAT", &encoder.finish().ok()?);
+        push_png_chunk(&mut png, b"IEND", &[]);
+        Some(png)
+    }
+
+    // JPEG and JPEG 2000 images are passed through, others are converted to PNG if possible and otherwise returned
+    // as they're stored in the PDF
+    fn image_data(doc: &lopdf::Document, image: &Stream, width: usize, height: usize) -> (&'static str, Vec<u8>) {
+        let filters = image.filters().unwrap_or_default();
+        match filters.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            ["DCTDecode"] => return ("image/jpeg", image.content.clone()),
+            ["JPXDecode"] => return ("image/jp2", image.content.clone()),
+            _ => (),
+        }
+        let png = || {
+            let color = png_color(doc, image.dict.get(b"ColorSpace").ok()?)?;
+            let bits = image.dict.get(b"BitsPerComponent").and_then(Object::as_i64).ok()?;
+            let samples = decoded_content(image)?;
+            png_from_samples(width, height, u8::try_from(bits).ok()?, &color, &samples)
+        };
+        match png() {
+            Some(png) => ("image/png", png),
+            None => ("application/octet-stream", image.content.clone()),
+        }
+    }
+
+    // a page without resources of its own inherits them from the page tree (the depth limit guards against cycles)
+    fn page_resources(doc: &lopdf::Document, page_id: ObjectId) -> Option<&Dictionary> {
+        let mut node = doc.get_dictionary(page_id).ok()?;
+        for _ in 0..64 {
+            if let Ok(resources) = doc.get_dict_in_dict(node, b"Resources") {
+                return Some(resources);
+            }
+            node = doc.get_dict_in_dict(node, b"Parent").ok()?;
+        }
+        None
+    }
+
+    // forms are self-contained pieces of content, such as a letterhead, which may draw images of their own
+    fn collect_images<'a>(
+        doc: &'a lopdf::Document,
+        resources: &'a Dictionary,
+        seen: &mut HashSet<ObjectId>,
+        images: &mut Vec<&'a Stream>,
+    ) {
+        let Ok(xobjects) = doc.get_dict_in_dict(resources, b"XObject") else {
+            return;
+        };
+        for (_, xobject) in xobjects.iter() {
+            let Ok((id, Object::Stream(stream))) = doc.dereference(xobject) else {
+                continue;
+            };
+            if id.is_some_and(|id| !seen.insert(id)) {
+                continue;
+            }
+            match stream.dict.get(b"Subtype").and_then(Object::as_name) {
+                Ok(b"Image") => images.push(stream),
+                Ok(b"Form") => {
+                    if let Ok(resources) = doc.get_dict_in_dict(&stream.dict, b"Resources") {
+                        collect_images(doc, resources, seen, images);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    pub(crate) fn pdf_images(document: &[u8]) -> Vec<ImageRow> {
+        let doc = load_pdf(document, None, "Error extracting images from PDF");
+        let mut rows = Vec::new();
+        for (page_number, page_id) in doc.get_pages() {
+            let mut images = Vec::new();
+            if let Some(resources) = page_resources(&doc, page_id) {
+                collect_images(&doc, resources, &mut HashSet::new(), &mut images);
+            }
+            for image in images {
+                let dimension = |key: &[u8]| {
+                    let value = image.dict.get(key).and_then(Object::as_i64).ok()?;
+                    i32::try_from(value).ok().filter(|value| *value > 0)
+                };
+                let (width, height) = (dimension(b"Width"), dimension(b"Height"));
+                let (mime_type, data) = match (width, height) {
+                    (Some(w), Some(h)) => image_data(&doc, image, w as usize, h as usize),
+                    _ => ("application/octet-stream", image.content.clone()),
+                };
+                // PDFs keep alt text in the structure tree, if at all, rather than with the image
+                rows.push((page_number as i32, mime_type.to_string(), width, height, None, data));
+            }
+        }
+        rows
+    }
+
+    #[allow(clippy::type_complexity)]
+    #[pg_extern(immutable, strict)]
+    pub fn images_from_pdf(
+        document: &[u8],
+    ) -> TableIterator<
+        'static,
+        (
+            name!(page_or_index, i32),
+            name!(mime_type, String),
+            name!(width, Option<i32>),
+            name!(height, Option<i32>),
+            name!(alt_text, Option<String>),
+            name!(data, Vec<u8>),
+        ),
+    > {
+        TableIterator::new(worker::extract("images_from_pdf", document.to_vec(), &[]))
+    }
+
+    // metadata
+
+    const INFO_KEYS: [(&str, &str); 6] = [
+        ("Title", "title"),
+        ("Author", "author"),
+        ("Subject", "subject"),
+        ("Keywords", "keywords"),
+        ("Creator", "creator"),
+        ("Producer", "producer"),
+    ];
+    const INFO_DATE_KEYS: [(&str, &str); 2] = [("CreationDate", "creation_date"), ("ModDate", "modification_date")];
+
+    // PDF dates look like D:YYYYMMDDHHmmSSOHH'mm' where everything after the year is optional
+    pub(super) fn iso_date_from_pdf_date(raw: &str) -> Option<String> {
+        let raw = raw.trim().strip_prefix("D:").unwrap_or(raw.trim());
+        let digits: String = raw.chars().take_while(|c| c.is_ascii_digit()).collect();
+        if ![4, 6, 8, 10, 12, 14].contains(&digits.len()) {
+            return None;
+        }
+        let field = |start: usize, default: &'static str| digits.get(start..start + 2).unwrap_or(default).to_string();
+        let mut iso = format!(
+            "{}-{}-{}T{}:{}:{}",
+            &digits[0..4],
+            field(4, "01"),
+            field(6, "01"),
+            field(8, "00"),
+            field(10, "00"),
+            field(12, "00")
+        );
+        let zone: String = raw[digits.len()..].chars().filter(|c| *c != '\'').collect();
+        match zone.chars().next() {
+            None | Some('Z') => iso.push('Z'),
+            Some(sign @ ('+' | '-')) => {
+                let number = |range| zone.get(range).filter(|part: &&str| part.bytes().all(|b| b.is_ascii_digit()));
+                let hours = number(1..3)?;
+                let minutes = match zone.len() {
+                    3 => "00",
+                    5 => number(3..5)?,
+                    _ => return None,
+                };
+                if hours > "23" || minutes > "59" {
+                    return None;
+                }
+                iso.push_str(&format!("{sign}{hours}:{minutes}"));
+            }
+            _ => return None,
+        }
+        Some(iso)
+    }
+
+    fn pdf_info(doc: &lopdf::Document) -> Map<String, Value> {
+        let info = doc
+            .trailer
+            .get(b"Info")
+            .and_then(|info| doc.dereference(info))
+            .and_then(|(_, info)| info.as_dict())
+            .ok();
+        let text = |key: &str| {
+            info.and_then(|info| info.get(key.as_bytes()).ok())
+                .and_then(|value| doc.dereference(value).ok())
+                .and_then(|(_, value)| lopdf::decode_text_string(value).ok())
+                .map(|value| value.nfkc().collect::<String>())
+        };
+
+        let mut map = Map::new();
+        for (pdf_key, json_key) in INFO_KEYS {
+            map.insert(json_key.to_string(), text(pdf_key).map_or(Value::Null, Value::String));
+        }
+        for (pdf_key, json_key) in INFO_DATE_KEYS {
+            let date = text(pdf_key).map(|date| iso_date_from_pdf_date(&date).unwrap_or(date));
+            map.insert(json_key.to_string(), date.map_or(Value::Null, Value::String));
+        }
+        map
+    }
+
+    fn pdf_outline(doc: &lopdf::Document) -> Vec<Value> {
+        match doc.get_toc() {
+            Err(_) => vec![], // includes the common case of a document that simply has no outline
+            Ok(toc) => toc
+                .toc
+                .into_iter()
+                .map(|entry| {
+                    json!({
+                        "level": entry.level,
+                        "title": entry.title.nfkc().collect::<String>(),
+                        "page": entry.page,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn pdf_metadata(document: &[u8]) -> pgrx::JsonB {
+        let doc = load_pdf(document, None, "Error reading PDF");
+        let page_count = doc.get_pages().len();
+        let mut metadata = pdf_info(&doc);
+        metadata.insert("page_count".to_string(), json!(page_count));
+        metadata.insert("outline".to_string(), Value::Array(pdf_outline(&doc)));
+        pgrx::JsonB(Value::Object(metadata))
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn metadata_from_pdf(document: &[u8]) -> pgrx::JsonB {
+        worker::extract_value("metadata_from_pdf", document.to_vec(), &[])
     }
 }
 
@@ -18,6 +543,7 @@ mod rag {
 mod tests {
     use super::rag::*;
     use pgrx::prelude::*;
+    use std::io::Read;
 
     #[pg_test]
     fn test_text_from_pdf() {
@@ -31,4 +557,237 @@ mod tests {
     fn test_text_from_not_pdf() {
         text_from_pdf(include_bytes!("../test_res/test.pages"));
     }
+
+    #[pg_test]
+    fn test_text_from_encrypted_pdf() {
+        let text = text_from_pdf_with_password(include_bytes!("../test_res/test_encrypted.pdf"), "secret");
+        assert!(text.contains("Secret text"));
+    }
+
+    #[pg_test(error = "[rag] PDF is encrypted: a password is required")]
+    fn test_text_from_encrypted_pdf_without_password() {
+        text_from_pdf(include_bytes!("../test_res/test_encrypted.pdf"));
+    }
+
+    #[pg_test(error = "[rag] Wrong password for encrypted PDF")]
+    fn test_text_from_encrypted_pdf_with_wrong_password() {
+        text_from_pdf_with_password(include_bytes!("../test_res/test_encrypted.pdf"), "guess");
+    }
+
+    #[pg_test(error = "[rag] Error extracting text from PDF: PDF error: Invalid file header")]
+    fn test_text_from_not_pdf_with_password() {
+        text_from_pdf_with_password(include_bytes!("../test_res/test.pages"), "secret");
+    }
+
+    #[pg_test]
+    fn test_pages_from_pdf() {
+        let pages: Vec<(i32, String)> = pages_from_pdf(include_bytes!("../test_res/test.pdf")).collect();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].0, 1);
+        assert!(pages[0].1.contains("Test PDF document"));
+        assert!(pages[0].1.contains("• One"));
+    }
+
+    #[pg_test(error = "[rag] Error extracting text from PDF page 2: MediaBox")]
+    fn test_pages_from_pdf_broken_page() {
+        pages_from_pdf(include_bytes!("../test_res/test_broken_page.pdf"));
+    }
+
+    #[pg_test(error = "[rag] Error extracting text from PDF: PDF error: Invalid file header")]
+    fn test_pages_from_not_pdf() {
+        pages_from_pdf(include_bytes!("../test_res/test.pages"));
+    }
+
+    #[pg_test]
+    fn test_blocks_from_pdf() {
+        let blocks: Vec<_> = blocks_from_pdf(include_bytes!("../test_res/test_layout.pdf"))
+            .map(|(page, block_index, kind, _, text)| (page, block_index, kind, text))
+            .collect();
+        let block = |block_index: i32, kind: &str, text: &str| (1, block_index, kind.to_string(), text.to_string());
+        assert_eq!(
+            blocks,
+            vec![
+                block(1, "heading", "Layout test document"),
+                block(
+                    2,
+                    "paragraph",
+                    "The left column starts here and\ncontinues onto a second line of\ntext before it ends."
+                ),
+                block(3, "paragraph", "A second paragraph in the left\ncolumn."),
+                block(
+                    4,
+                    "paragraph",
+                    "The right column is read only\nafter the whole of the left\ncolumn has been read."
+                ),
+                block(
+                    5,
+                    "table",
+                    "| Name | Role | Year |\n| --- | --- | --- |\n| Ada | Engineer | 1843 |\n| Charles | Inventor \\| builder | 1822 |"
+                ),
+            ]
+        );
+    }
+
+    #[pg_test]
+    fn test_blocks_from_pdf_bbox() {
+        let (_, _, _, bbox, _) = blocks_from_pdf(include_bytes!("../test_res/test_layout.pdf"))
+            .next()
+            .unwrap();
+        // the title is set in 20pt type with its baseline 720pt up from the bottom of a 792pt page
+        assert_eq!(bbox[0], 72.0);
+        assert_eq!(bbox[1], 792.0 - 720.0 - 20.0 * 0.8);
+        assert_eq!(bbox[3], 792.0 - 720.0 + 20.0 * 0.2);
+    }
+
+    #[pg_test]
+    fn test_blocks_from_pdf_offset_media_box() {
+        let blocks: Vec<_> = blocks_from_pdf(include_bytes!("../test_res/test_layout_offset.pdf"))
+            .map(|(_, _, kind, bbox, text)| (kind, bbox, text))
+            .collect();
+        // the media box is [100 200 712 992], and the title's baseline is 72pt from the left and 72pt from the top
+        let (kind, bbox, text) = &blocks[0];
+        assert_eq!((kind.as_str(), text.as_str()), ("heading", "Offset media box"));
+        assert_eq!(bbox[0], 72.0);
+        assert_eq!(bbox[1], 72.0 - 20.0 * 0.8);
+        assert_eq!(bbox[3], 72.0 + 20.0 * 0.2);
+        assert_eq!(blocks[1].2, "The lower left corner of this page is not at the origin.");
+    }
+
+    #[pg_test(error = "[rag] Error extracting text from PDF: PDF error: Invalid file header")]
+    fn test_blocks_from_not_pdf() {
+        blocks_from_pdf(include_bytes!("../test_res/test.pages"));
+    }
+
+    #[pg_test]
+    fn test_images_from_pdf() {
+        let images: Vec<_> = images_from_pdf(include_bytes!("../test_res/test.pdf")).collect();
+        assert_eq!(images.len(), 1);
+        let (page, mime_type, width, height, alt_text, data) = &images[0];
+        assert_eq!(
+            (*page, mime_type.as_str(), *width, *height, alt_text.as_deref()),
+            (1, "image/jpeg", Some(1253), Some(1600), None)
+        );
+        assert!(data.starts_with(&[0xff, 0xd8]));
+    }
+
+    // the decompressed contents of a PNG's IDAT chunk
+    fn png_image_data(png: &[u8]) -> Vec<u8> {
+        let mut at = 8;
+        loop {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            if &png[at + 4..at + 8] == b"IDAT" {
+                let mut data = Vec::new();
+                flate2::read::ZlibDecoder::new(&png[at + 8..at + 8 + len])
+                    .read_to_end(&mut data)
+                    .unwrap();
+                return data;
+            }
+            at += len + 12;
+        }
+    }
+
+    #[pg_test]
+    fn test_images_from_pdf_converted() {
+        let images: Vec<_> = images_from_pdf(include_bytes!("../test_res/test_images.pdf")).collect();
+        let summary: Vec<_> = images
+            .iter()
+            .map(|(page, mime_type, width, height, _, _)| (*page, mime_type.as_str(), *width, *height))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, "image/png", Some(2), Some(2)),
+                (2, "image/png", Some(3), Some(1)),
+                (2, "application/octet-stream", Some(1), Some(1)),
+            ]
+        );
+        // each row of samples is preceded by its filter type, 0
+        assert_eq!(
+            png_image_data(&images[0].5),
+            vec![0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 255, 255, 255, 255]
+        );
+        assert_eq!(png_image_data(&images[1].5), vec![0, 0b01000000]);
+        assert!(images[1].5.windows(4).any(|window| window == b"PLTE"));
+        assert_eq!(images[2].5, vec![0, 255, 255, 0]); // CMYK can't be represented in PNG
+    }
+
+    fn flate_stream(content: &[u8]) -> lopdf::Stream {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, content).unwrap();
+        let dict = lopdf::Dictionary::from_iter([("Filter", lopdf::Object::Name(b"FlateDecode".to_vec()))]);
+        lopdf::Stream::new(dict, encoder.finish().unwrap())
+    }
+
+    #[pg_test]
+    fn test_decoded_content() {
+        let stream = flate_stream(&[7; 1000]);
+        assert_eq!(decoded_content_with_limit(&stream, 1000), Some(vec![7; 1000]));
+        assert_eq!(decoded_content_with_limit(&stream, 0), Some(vec![7; 1000]));
+
+        let content = weezl::encode::Encoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8)
+            .encode(&[7; 1000])
+            .unwrap();
+        let dict = lopdf::Dictionary::from_iter([("Filter", lopdf::Object::Name(b"LZWDecode".to_vec()))]);
+        let stream = lopdf::Stream::new(dict, content);
+        assert_eq!(decoded_content_with_limit(&stream, 1000), Some(vec![7; 1000]));
+    }
+
+    #[pg_test(error = "[rag] Image in PDF is too large when decompressed (rag.max_document_bytes is 999)")]
+    fn test_decoded_content_too_large() {
+        decoded_content_with_limit(&flate_stream(&[7; 1000]), 999);
+    }
+
+    #[pg_test(error = "[rag] Error extracting images from PDF: PDF error: Invalid file header")]
+    fn test_images_from_not_pdf() {
+        images_from_pdf(include_bytes!("../test_res/test.pages"));
+    }
+
+    #[pg_test]
+    fn test_metadata_from_pdf() {
+        let metadata = metadata_from_pdf(include_bytes!("../test_res/test.pdf")).0;
+        assert_eq!(metadata["title"], "test.pages");
+        assert_eq!(metadata["author"], "George MacKerron");
+        assert_eq!(metadata["subject"], serde_json::Value::Null);
+        assert_eq!(metadata["creation_date"], "2024-08-22T20:05:55Z");
+        assert_eq!(metadata["page_count"], 1);
+        assert_eq!(metadata["outline"], serde_json::json!([]));
+    }
+
+    #[pg_test]
+    fn test_iso_date_from_pdf_date() {
+        let iso = |raw| iso_date_from_pdf_date(raw);
+        assert_eq!(iso("D:20240822200555Z").as_deref(), Some("2024-08-22T20:05:55Z"));
+        assert_eq!(iso("D:20240822200555+05'30'").as_deref(), Some("2024-08-22T20:05:55+05:30"));
+        assert_eq!(iso("D:202408-08").as_deref(), Some("2024-08-01T00:00:00-08:00"));
+        assert_eq!(iso("D:2024").as_deref(), Some("2024-01-01T00:00:00Z"));
+        assert_eq!(iso("D:20240822+é5"), None);
+        assert_eq!(iso("D:20240822+0é"), None);
+        assert_eq!(iso("D:20240822+ab'cd'"), None);
+        assert_eq!(iso("D:20240822+05'3"), None);
+        assert_eq!(iso("D:20240822+05'30'00"), None);
+        assert_eq!(iso("D:20240822+25'00'"), None);
+        assert_eq!(iso("D:20240822 tomorrow"), None);
+    }
+
+    #[pg_test]
+    fn test_metadata_from_owner_password_pdf() {
+        let metadata = metadata_from_pdf(include_bytes!("../test_res/test_owner_password.pdf")).0;
+        assert_eq!(metadata["title"], "Owner password only");
+        assert_eq!(metadata["author"], "Ada");
+        assert_eq!(metadata["page_count"], 1);
+        assert_eq!(
+            metadata["outline"],
+            serde_json::json!([{"level": 1, "title": "Introduction", "page": 1}])
+        );
+    }
+
+    #[pg_test(error = "[rag] PDF is encrypted: a password is required")]
+    fn test_metadata_from_encrypted_pdf() {
+        metadata_from_pdf(include_bytes!("../test_res/test_encrypted.pdf"));
+    }
+
+    #[pg_test(error = "[rag] Error reading PDF: PDF error: Invalid file header")]
+    fn test_metadata_from_not_pdf() {
+        metadata_from_pdf(include_bytes!("../test_res/test.pages"));
+    }
 }
@@ -0,0 +1,444 @@
+// Layout analysis for PDFs: pdf_extract reports where each character is drawn, and from that we reconstruct lines,
+// paragraphs and simple tables, and an order in which to read them. Coordinates are in points, measured from the top
+// left of the page. None of this calls into Postgres, so it can run on an extraction thread.
+
+use pdf_extract::{MediaBox, OutputDev, OutputError, Transform};
+
+// thresholds, as multiples of the font size
+const SAME_BASELINE: f64 = 0.3;
+const WORD_GAP: f64 = 0.15;
+const FRAGMENT_GAP: f64 = 1.2;
+const PARAGRAPH_LINE_SPACING: f64 = 1.7;
+const TABLE_ROW_SPACING: f64 = 2.5;
+const ASCENT: f64 = 0.8;
+const DESCENT: f64 = 0.2;
+
+const HEADING_SIZE_RATIO: f64 = 1.15;
+const MAX_HEADING_LINES: usize = 3;
+const MAX_TABLE_CELL_WORDS: usize = 4;
+const FULL_WIDTH_RATIO: f64 = 0.6;
+
+struct Glyph {
+    x: f64,
+    y: f64, // baseline
+    width: f64,
+    size: f64,
+    text: String,
+}
+
+// a run of text on one baseline, with no big gaps
+struct Fragment {
+    bbox: [f64; 4],
+    y: f64,
+    size: f64,
+    text: String,
+}
+
+impl Fragment {
+    fn new(glyph: &Glyph) -> Self {
+        Fragment {
+            bbox: [
+                glyph.x,
+                glyph.y - glyph.size * ASCENT,
+                glyph.x + glyph.width,
+                glyph.y + glyph.size * DESCENT,
+            ],
+            y: glyph.y,
+            size: glyph.size,
+            text: glyph.text.clone(),
+        }
+    }
+}
+
+pub struct Block {
+    pub kind: &'static str,
+    pub bbox: [f64; 4],
+    pub text: String,
+}
+
+fn union(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])]
+}
+
+fn overlaps_horizontally(a: [f64; 4], b: [f64; 4]) -> bool {
+    a[0] < b[2] && b[0] < a[2]
+}
+
+// collects the characters on each page, with their positions
+
+#[derive(Default)]
+pub struct LayoutOutput {
+    pages: Vec<(u32, Vec<Glyph>)>,
+    // the media box's top left corner, which is where page coordinates start
+    page_top: f64,
+    page_left: f64,
+}
+
+impl OutputDev for LayoutOutput {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        _: Option<(f64, f64, f64, f64)>,
+    ) -> Result<(), OutputError> {
+        self.page_top = media_box.ury;
+        self.page_left = media_box.llx;
+        self.pages.push((page_num, Vec::new()));
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        _spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> Result<(), OutputError> {
+        // spaces are inferred from the gaps between characters
+        if char.trim().is_empty() {
+            return Ok(());
+        }
+        let size = font_size * (trm.m11 * trm.m22 - trm.m12 * trm.m21).abs().sqrt();
+        if let Some((_, glyphs)) = self.pages.last_mut() {
+            glyphs.push(Glyph {
+                x: trm.m31 - self.page_left,
+                y: self.page_top - trm.m32,
+                width: width * size,
+                size,
+                text: char.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+impl LayoutOutput {
+    pub fn into_blocks(self) -> Vec<(u32, Vec<Block>)> {
+        self.pages
+            .into_iter()
+            .map(|(page_num, glyphs)| (page_num, page_blocks(&glyphs)))
+            .collect()
+    }
+}
+
+// fragments
+
+fn fragments(glyphs: &[Glyph]) -> Vec<Fragment> {
+    let mut fragments: Vec<Fragment> = Vec::new();
+    for glyph in glyphs {
+        if let Some(fragment) = fragments.last_mut() {
+            let size = fragment.size.min(glyph.size);
+            let gap = glyph.x - fragment.bbox[2];
+            if (glyph.y - fragment.y).abs() < size * SAME_BASELINE && gap > -size && gap < size * FRAGMENT_GAP {
+                if gap > size * WORD_GAP {
+                    fragment.text.push(' ');
+                }
+                fragment.text.push_str(&glyph.text);
+                fragment.bbox = union(fragment.bbox, Fragment::new(glyph).bbox);
+                continue;
+            }
+        }
+        fragments.push(Fragment::new(glyph));
+    }
+    fragments
+}
+
+// tables
+
+fn rows(fragments: &[Fragment]) -> Vec<Vec<usize>> {
+    let mut indices: Vec<usize> = (0..fragments.len()).collect();
+    indices.sort_by(|&a, &b| fragments[a].y.total_cmp(&fragments[b].y));
+    let mut rows: Vec<Vec<usize>> = Vec::new();
+    for index in indices {
+        let fragment = &fragments[index];
+        match rows.last_mut() {
+            Some(row) if (fragments[row[0]].y - fragment.y).abs() < fragment.size * SAME_BASELINE => row.push(index),
+            _ => rows.push(vec![index]),
+        }
+    }
+    for row in &mut rows {
+        row.sort_by(|&a, &b| fragments[a].bbox[0].total_cmp(&fragments[b].bbox[0]));
+    }
+    rows
+}
+
+fn is_list_marker(text: &str) -> bool {
+    matches!(text, "•" | "◦" | "▪" | "-" | "–" | "*")
+        || text
+            .strip_suffix(['.', ')'])
+            .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+// a row can be part of a table if it has at least two short cells, which don't look like a list item
+fn is_table_row(fragments: &[Fragment], row: &[usize]) -> bool {
+    row.len() >= 2
+        && !is_list_marker(&fragments[row[0]].text)
+        && row
+            .iter()
+            .all(|&index| fragments[index].text.split_whitespace().count() <= MAX_TABLE_CELL_WORDS)
+}
+
+// each column of the row must overlap the same column of the row above, and no other
+fn aligns_with(fragments: &[Fragment], row: &[usize], above: &[usize]) -> bool {
+    row.len() == above.len()
+        && row.iter().enumerate().all(|(column, &index)| {
+            above.iter().enumerate().all(|(other_column, &other_index)| {
+                overlaps_horizontally(fragments[index].bbox, fragments[other_index].bbox) == (column == other_column)
+            })
+        })
+        && fragments[row[0]].y - fragments[above[0]].y < fragments[row[0]].size * TABLE_ROW_SPACING
+}
+
+fn markdown_table(fragments: &[Fragment], rows: &[Vec<usize>]) -> String {
+    let cell = |index: usize| fragments[index].text.replace('|', "\\|");
+    let line = |row: &Vec<usize>| {
+        format!(
+            "| {} |",
+            row.iter().map(|&index| cell(index)).collect::<Vec<_>>().join(" | ")
+        )
+    };
+    let mut lines = vec![line(&rows[0]), format!("|{}", " --- |".repeat(rows[0].len()))];
+    lines.extend(rows[1..].iter().map(line));
+    lines.join("\n")
+}
+
+// returns the tables, and which of the fragments are in them
+fn tables(fragments: &[Fragment]) -> (Vec<Block>, Vec<bool>) {
+    let mut tables = Vec::new();
+    let mut used = vec![false; fragments.len()];
+    let mut run: Vec<Vec<usize>> = Vec::new();
+    let mut end_run = |run: &mut Vec<Vec<usize>>, used: &mut Vec<bool>| {
+        if run.len() >= 2 {
+            let bbox = run
+                .iter()
+                .flatten()
+                .map(|&index| fragments[index].bbox)
+                .reduce(union)
+                .unwrap();
+            tables.push(Block {
+                kind: "table",
+                bbox,
+                text: markdown_table(fragments, run),
+            });
+            run.iter().flatten().for_each(|&index| used[index] = true);
+        }
+        run.clear();
+    };
+    for row in rows(fragments) {
+        if !is_table_row(fragments, &row) {
+            end_run(&mut run, &mut used);
+            continue;
+        }
+        if run.last().is_some_and(|above| !aligns_with(fragments, &row, above)) {
+            end_run(&mut run, &mut used);
+        }
+        run.push(row);
+    }
+    end_run(&mut run, &mut used);
+    (tables, used)
+}
+
+// paragraphs and headings
+
+struct Paragraph {
+    lines: Vec<Fragment>,
+    bbox: [f64; 4],
+}
+
+fn paragraphs(fragments: Vec<Fragment>) -> Vec<Paragraph> {
+    let mut fragments = fragments;
+    fragments.sort_by(|a, b| a.y.total_cmp(&b.y).then(a.bbox[0].total_cmp(&b.bbox[0])));
+    let mut paragraphs: Vec<Paragraph> = Vec::new();
+    for fragment in fragments {
+        // continue the nearest paragraph whose last line is just above, in the same column, in the same size of text
+        let continues = paragraphs
+            .iter_mut()
+            .filter(|paragraph| {
+                let last = paragraph.lines.last().unwrap();
+                let spacing = fragment.y - last.y;
+                spacing > 0.0
+                    && spacing < last.size * PARAGRAPH_LINE_SPACING
+                    && (fragment.size - last.size).abs() < last.size * 0.1
+                    && overlaps_horizontally(fragment.bbox, paragraph.bbox)
+            })
+            .min_by(|a, b| {
+                (fragment.y - a.lines.last().unwrap().y).total_cmp(&(fragment.y - b.lines.last().unwrap().y))
+            });
+        match continues {
+            Some(paragraph) => {
+                paragraph.bbox = union(paragraph.bbox, fragment.bbox);
+                paragraph.lines.push(fragment);
+            }
+            None => paragraphs.push(Paragraph {
+                bbox: fragment.bbox,
+                lines: vec![fragment],
+            }),
+        }
+    }
+    paragraphs
+}
+
+// the size of most of the text on the page
+fn body_size(fragments: &[Fragment]) -> f64 {
+    let mut sizes: Vec<(f64, usize)> = Vec::new();
+    for fragment in fragments {
+        let chars = fragment.text.chars().count();
+        match sizes.iter_mut().find(|(size, _)| (size - fragment.size).abs() < 0.5) {
+            Some((_, count)) => *count += chars,
+            None => sizes.push((fragment.size, chars)),
+        }
+    }
+    sizes
+        .into_iter()
+        .max_by_key(|&(_, count)| count)
+        .map_or(0.0, |(size, _)| size)
+}
+
+// reading order: blocks that span most of the page divide it into bands, and within each band, we read down each
+// column in turn, where a column is a set of blocks that overlap horizontally
+
+fn reading_order(blocks: Vec<Block>) -> Vec<Block> {
+    let Some(left) = blocks.iter().map(|block| block.bbox[0]).reduce(f64::min) else {
+        return blocks;
+    };
+    let right = blocks.iter().map(|block| block.bbox[2]).fold(left, f64::max);
+    let full_width = (right - left) * FULL_WIDTH_RATIO;
+
+    let mut blocks = blocks;
+    blocks.sort_by(|a, b| a.bbox[1].total_cmp(&b.bbox[1]));
+    let mut ordered = Vec::new();
+    let mut band: Vec<Block> = Vec::new();
+    for block in blocks {
+        if block.bbox[2] - block.bbox[0] >= full_width {
+            ordered.extend(columns(std::mem::take(&mut band)));
+            ordered.push(block);
+        } else {
+            band.push(block);
+        }
+    }
+    ordered.extend(columns(band));
+    ordered
+}
+
+fn columns(band: Vec<Block>) -> Vec<Block> {
+    let mut columns: Vec<(f64, f64, Vec<Block>)> = Vec::new(); // left, right, blocks
+    for block in band {
+        let overlapping: Vec<usize> = (0..columns.len())
+            .filter(|&i| block.bbox[0] < columns[i].1 && columns[i].0 < block.bbox[2])
+            .collect();
+        let mut column = (block.bbox[0], block.bbox[2], vec![block]);
+        // a block may bridge two columns found so far, in which case they're really one
+        for &i in overlapping.iter().rev() {
+            let (left, right, blocks) = columns.remove(i);
+            column.0 = column.0.min(left);
+            column.1 = column.1.max(right);
+            column.2.extend(blocks);
+        }
+        columns.push(column);
+    }
+    columns.sort_by(|a, b| a.0.total_cmp(&b.0));
+    columns
+        .into_iter()
+        .flat_map(|(_, _, mut blocks)| {
+            blocks.sort_by(|a, b| a.bbox[1].total_cmp(&b.bbox[1]));
+            blocks
+        })
+        .collect()
+}
+
+fn page_blocks(glyphs: &[Glyph]) -> Vec<Block> {
+    let fragments = fragments(glyphs);
+    let body_size = body_size(&fragments);
+    let (mut blocks, in_table) = tables(&fragments);
+    let remaining: Vec<Fragment> = fragments
+        .into_iter()
+        .zip(in_table)
+        .filter_map(|(fragment, in_table)| (!in_table).then_some(fragment))
+        .collect();
+    for paragraph in paragraphs(remaining) {
+        let size = paragraph.lines[0].size;
+        let kind = match size >= body_size * HEADING_SIZE_RATIO && paragraph.lines.len() <= MAX_HEADING_LINES {
+            true => "heading",
+            false => "paragraph",
+        };
+        let text = paragraph
+            .lines
+            .iter()
+            .map(|line| line.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        blocks.push(Block {
+            kind,
+            bbox: paragraph.bbox,
+            text,
+        });
+    }
+    reading_order(blocks)
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+    use pgrx::prelude::*;
+
+    fn fragment(x: f64, y: f64, text: &str) -> Fragment {
+        let width = text.len() as f64 * 5.0;
+        Fragment {
+            bbox: [x, y - 8.0, x + width, y + 2.0],
+            y,
+            size: 10.0,
+            text: text.to_string(),
+        }
+    }
+
+    #[pg_test]
+    fn test_tables_ignore_lists() {
+        let fragments = vec![
+            fragment(72.0, 100.0, "•"),
+            fragment(90.0, 100.0, "One"),
+            fragment(72.0, 114.0, "•"),
+            fragment(90.0, 114.0, "Two"),
+        ];
+        let (tables, in_table) = tables(&fragments);
+        assert!(tables.is_empty());
+        assert_eq!(in_table, vec![false; 4]);
+    }
+
+    #[pg_test]
+    fn test_reading_order() {
+        let block = |x: f64, y: f64, width: f64, text: &str| Block {
+            kind: "paragraph",
+            bbox: [x, y, x + width, y + 10.0],
+            text: text.to_string(),
+        };
+        let blocks = vec![
+            block(300.0, 50.0, 200.0, "right top"),
+            block(50.0, 50.0, 200.0, "left top"),
+            block(50.0, 10.0, 450.0, "title"),
+            block(50.0, 80.0, 200.0, "left bottom"),
+            block(50.0, 200.0, 450.0, "footer"),
+        ];
+        let texts: Vec<_> = reading_order(blocks).into_iter().map(|block| block.text).collect();
+        assert_eq!(texts, vec!["title", "left top", "left bottom", "right top", "footer"]);
+    }
+}
@@ -0,0 +1,166 @@
+use pgrx::prelude::*;
+
+#[pg_schema]
+pub(crate) mod rag {
+    use super::super::errors::*;
+    use super::super::package::*;
+    use super::super::worker;
+    use pgrx::prelude::*;
+    use roxmltree::{Document, Node};
+
+    const NS_DRAWING: &str = "http://schemas.openxmlformats.org/drawingml/2006/main";
+    const NS_PRESENTATION: &str = "http://schemas.openxmlformats.org/presentationml/2006/main";
+    const NS_RELATIONSHIPS: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+    fn placeholder_type<'a>(shape: Node<'a, '_>) -> Option<&'a str> {
+        let placeholder = shape
+            .descendants()
+            .find(|node| node.has_tag_name((NS_PRESENTATION, "ph")))?;
+        // a placeholder with no type attribute is a body placeholder
+        Some(placeholder.attribute("type").unwrap_or("body"))
+    }
+
+    fn paragraph_text(paragraph: Node) -> String {
+        paragraph
+            .descendants()
+            .filter_map(|node| match node.tag_name().name() {
+                "t" if node.tag_name().namespace() == Some(NS_DRAWING) => node.text(),
+                "br" if node.tag_name().namespace() == Some(NS_DRAWING) => Some("\n"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // returns (title, other text) for a slide or notes page, skipping placeholders of the given types
+    fn shape_texts(part: &Document, skip_placeholders: &[&str]) -> (Vec<String>, Vec<String>) {
+        let mut title = Vec::new();
+        let mut other = Vec::new();
+        for paragraph in part.descendants().filter(|node| node.has_tag_name((NS_DRAWING, "p"))) {
+            let placeholder = paragraph
+                .ancestors()
+                .find(|node| node.has_tag_name((NS_PRESENTATION, "sp")))
+                .and_then(placeholder_type);
+            if placeholder.is_some_and(|placeholder| skip_placeholders.contains(&placeholder)) {
+                continue;
+            }
+            let text = paragraph_text(paragraph);
+            if text.trim().is_empty() {
+                continue;
+            }
+            match placeholder {
+                Some("title" | "ctrTitle") => title.push(text),
+                _ => other.push(text),
+            }
+        }
+        (title, other)
+    }
+
+    fn non_empty(texts: Vec<String>) -> Option<String> {
+        match texts.is_empty() {
+            true => None,
+            false => Some(texts.join("\n")),
+        }
+    }
+
+    pub(crate) fn pptx_slides(document: Vec<u8>) -> Vec<(i32, Option<String>, String, Option<String>)> {
+        let mut pptx = Package::new(document, ".pptx file");
+
+        let presentation_path = "ppt/presentation.xml";
+        let presentation_xml = pptx
+            .read(presentation_path)
+            .unwrap_or_pg_err("Couldn't find presentation in .pptx file");
+        let presentation = pptx.parse(&presentation_xml, presentation_path);
+        let presentation_rels = pptx.relationships(presentation_path);
+
+        // slides are listed in presentation order, which needn't match their file names
+        let slide_paths: Vec<String> = presentation
+            .descendants()
+            .filter(|node| node.has_tag_name((NS_PRESENTATION, "sldId")))
+            .filter_map(|node| node.attribute((NS_RELATIONSHIPS, "id")))
+            .filter_map(|id| presentation_rels.get(id))
+            .map(|(_, target)| target.clone())
+            .collect();
+
+        let mut rows = Vec::new();
+        for (index, slide_path) in slide_paths.iter().enumerate() {
+            let slide_xml = pptx
+                .read(slide_path)
+                .unwrap_or_pg_err("Couldn't find slide in .pptx file");
+            let slide = pptx.parse(&slide_xml, slide_path);
+            let (title, body) = shape_texts(&slide, &[]);
+
+            let notes_path = pptx
+                .relationships(slide_path)
+                .into_values()
+                .find(|(ty, _)| ty.ends_with("/notesSlide"))
+                .map(|(_, target)| target);
+            let notes = notes_path.and_then(|notes_path| {
+                let notes_xml = pptx.read(&notes_path)?;
+                let notes = pptx.parse(&notes_xml, &notes_path);
+                // notes pages repeat the slide image and can carry a slide number, header, footer and date
+                let (_, text) = shape_texts(&notes, &["sldImg", "sldNum", "hdr", "ftr", "dt"]);
+                non_empty(text)
+            });
+
+            rows.push((index as i32 + 1, non_empty(title), body.join("\n"), notes));
+        }
+        rows
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn text_from_pptx(
+        document: Vec<u8>,
+    ) -> TableIterator<
+        'static,
+        (
+            name!(slide, i32),
+            name!(title, Option<String>),
+            name!(body, String),
+            name!(notes, Option<String>),
+        ),
+    > {
+        TableIterator::new(worker::extract("text_from_pptx", document, &[]))
+    }
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_text_from_pptx() {
+        let slides: Vec<_> = text_from_pptx(include_bytes!("../test_res/test.pptx").to_vec()).collect();
+        assert_eq!(
+            slides,
+            vec![
+                (
+                    1,
+                    Some("Quarterly review".to_string()),
+                    "Prepared by the team".to_string(),
+                    None
+                ),
+                (
+                    2,
+                    Some("Results".to_string()),
+                    "Revenue up\nCosts down\nGrouped note\nQ1\n10\nQ2\n12".to_string(),
+                    Some("Mention the new pricing".to_string())
+                ),
+                (3, None, "No title here".to_string(), None),
+            ]
+        );
+    }
+
+    #[pg_test(error = "[rag] Couldn't read .pptx file: invalid Zip archive: Could not find EOCD")]
+    fn test_text_from_not_pptx() {
+        text_from_pptx(include_bytes!("../test_res/test.pdf").to_vec());
+    }
+
+    #[pg_test(error = "[rag] Couldn't find presentation in .pptx file")]
+    fn test_text_from_docx_as_pptx() {
+        text_from_pptx(include_bytes!("../test_res/test.docx").to_vec());
+    }
+}
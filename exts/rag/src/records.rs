@@ -0,0 +1,257 @@
+use crate::errors::*;
+use crate::xpath::{self, XPath};
+use minijinja::Environment;
+use pgrx::prelude::*;
+use serde_json::{Map, Value};
+
+// bounds the work a template can do (e.g. in nested loops), since rendering can't be interrupted
+const TEMPLATE_FUEL: u64 = 10_000_000;
+
+// Templates use Jinja syntax (as implemented by MiniJinja). An object's keys are available as variables, and any other
+// value as `value`. Block tags on lines of their own don't leave blank lines behind.
+fn render_template(template: &str, value: &Value) -> String {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_fuel(Some(TEMPLATE_FUEL));
+    let template = env.template_from_str(template).expect_or_pg_err("Invalid template");
+    let rendered = match value {
+        Value::Object(_) => template.render(value),
+        _ => template.render(minijinja::context! { value => minijinja::Value::from_serialize(value) }),
+    };
+    rendered.expect_or_pg_err("Error rendering template")
+}
+
+// one node gives its text, with whitespace collapsed, several give an array of their texts, and none gives null
+fn xpath_result(texts: Vec<String>) -> Value {
+    let mut texts: Vec<Value> = texts
+        .iter()
+        .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" ").into())
+        .collect();
+    match texts.len() {
+        0 => Value::Null,
+        1 => texts.remove(0),
+        _ => Value::Array(texts),
+    }
+}
+
+// Evaluates each named XPath (from the subset described in xpath.rs) against the document.
+fn values_from_xml(document: &str, xpath_map: Value) -> Map<String, Value> {
+    let Value::Object(xpath_map) = xpath_map else {
+        error!("{ERR_PREFIX} xpath_map must be a JSON object")
+    };
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    let document = roxmltree::Document::parse_with_options(document, options).expect_or_pg_err("Invalid XML");
+    let namespaces = xpath::namespaces(&document);
+    xpath_map
+        .into_iter()
+        .map(|(name, xpath)| {
+            let Value::String(xpath) = xpath else {
+                error!("{ERR_PREFIX} XPath for {name} must be a string")
+            };
+            let texts = XPath::parse(&xpath, &namespaces)
+                .expect_or_pg_err(&format!("Invalid XPath for {name}"))
+                .evaluate(&document);
+            (name, xpath_result(texts))
+        })
+        .collect()
+}
+
+// one "name: value" line for each value found, in order of name, with multiple values separated by commas
+fn labelled_lines(values: &Map<String, Value>) -> String {
+    let text = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    let mut names: Vec<&String> = values.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let value = match &values[name] {
+                Value::Null => return None,
+                Value::Array(items) => items.iter().map(text).collect::<Vec<_>>().join(", "),
+                value => text(value),
+            };
+            (!value.is_empty()).then(|| format!("{name}: {value}"))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[pg_schema]
+mod rag {
+    use super::{labelled_lines, render_template, values_from_xml};
+    use pgrx::prelude::*;
+
+    #[pg_extern(immutable, strict)]
+    pub fn text_from_json(value: pgrx::JsonB, template: &str) -> String {
+        render_template(template, &value.0)
+    }
+
+    // not strict, since template may be NULL, so a NULL document or xpath_map gives NULL here
+    #[pg_extern(immutable)]
+    pub fn _text_from_xml(
+        document: Option<&str>,
+        xpath_map: Option<pgrx::JsonB>,
+        template: Option<&str>,
+    ) -> Option<String> {
+        let values = values_from_xml(document?, xpath_map?.0);
+        Some(match template {
+            Some(template) => render_template(template, &serde_json::Value::Object(values)),
+            None => labelled_lines(&values),
+        })
+    }
+
+    extension_sql!(
+        "CREATE FUNCTION rag.text_from_xml(document xml, xpath_map jsonb, template text DEFAULT NULL) RETURNS text
+        LANGUAGE SQL IMMUTABLE AS $$
+            SELECT rag._text_from_xml(document::text, xpath_map, template);
+        $$;",
+        name = "text_from_xml",
+        requires = [_text_from_xml],
+    );
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+    use serde_json::json;
+
+    const TICKET_TEMPLATE: &str = "Ticket {{ id }}: {{ title }}
+{% if priority == \"high\" %}
+Urgent!
+{% endif %}
+{% for comment in comments %}
+- {{ comment.author }}: {{ comment.text }}
+{% else %}
+No comments
+{% endfor %}
+Tags: {{ tags | join(\", \") }}{{ missing }}";
+
+    #[pg_test]
+    fn test_text_from_json() {
+        let ticket = json!({
+            "id": 42,
+            "title": "Login fails",
+            "priority": "high",
+            "comments": [{"author": "Ann", "text": "Can't reproduce"}, {"author": "Bo", "text": "Fixed"}],
+            "tags": ["auth", "web"],
+        });
+        assert_eq!(
+            text_from_json(pgrx::JsonB(ticket), TICKET_TEMPLATE),
+            "Ticket 42: Login fails\nUrgent!\n- Ann: Can't reproduce\n- Bo: Fixed\nTags: auth, web"
+        );
+        let ticket = json!({"id": 43, "title": "Typo", "priority": "low", "comments": [], "tags": []});
+        assert_eq!(
+            text_from_json(pgrx::JsonB(ticket), TICKET_TEMPLATE),
+            "Ticket 43: Typo\nNo comments\nTags: "
+        );
+    }
+
+    #[pg_test]
+    fn test_text_from_json_not_object() {
+        assert_eq!(
+            text_from_json(
+                pgrx::JsonB(json!([1, 2, 3])),
+                "{{ value | sum }} from {{ value | length }}"
+            ),
+            "6 from 3"
+        )
+    }
+
+    #[pg_test(
+        error = "[rag] Invalid template: syntax error: unexpected end of input, expected end of block (in <string>:1)"
+    )]
+    fn test_text_from_json_invalid_template() {
+        text_from_json(pgrx::JsonB(json!({})), "{% if x %}");
+    }
+
+    #[pg_test(error = "[rag] Error rendering template: engine ran out of fuel (in <string>:1)")]
+    fn test_text_from_json_runaway_template() {
+        text_from_json(
+            pgrx::JsonB(json!({})),
+            "{% for i in range(10000) %}{% for j in range(10000) %}.{% endfor %}{% endfor %}",
+        );
+    }
+
+    const PRODUCT: &str = "<?xml version=\"1.0\"?>
+        <catalog xmlns:p=\"http://example.com/pricing\">
+          <product sku=\"A-1\">
+            <name>Espresso   cup</name>
+            <tag>kitchen</tag><tag>coffee</tag>
+            <p:price currency=\"EUR\">12.50</p:price>
+          </product>
+        </catalog>";
+
+    fn xpath_map() -> pgrx::JsonB {
+        pgrx::JsonB(json!({
+            "sku": "/catalog/product/@sku",
+            "name": "//product/name",
+            "tags": "//tag",
+            "price": "//p:price[@currency='EUR']",
+            "first_tag": "/catalog/product[1]/tag[1]",
+            "colour": "//colour",
+        }))
+    }
+
+    #[pg_test]
+    fn test_text_from_xml() {
+        assert_eq!(
+            _text_from_xml(Some(PRODUCT), Some(xpath_map()), None).unwrap(),
+            "first_tag: kitchen\nname: Espresso cup\nprice: 12.50\nsku: A-1\ntags: kitchen, coffee"
+        )
+    }
+
+    #[pg_test]
+    fn test_text_from_xml_with_template() {
+        assert_eq!(
+            _text_from_xml(
+                Some(PRODUCT),
+                Some(xpath_map()),
+                Some("{{ name }} ({{ sku }}), EUR {{ price }}{% if colour %}, {{ colour }}{% endif %}")
+            )
+            .unwrap(),
+            "Espresso cup (A-1), EUR 12.50"
+        )
+    }
+
+    #[pg_test]
+    fn test_text_from_xml_nested_namespace() {
+        let document = "<feed><entry xmlns:dc=\"http://purl.org/dc/elements/1.1/\"><dc:creator>Ann</dc:creator></entry>
+            <entry xmlns:dc=\"http://example.com/other\"><dc:creator>Bo</dc:creator></entry></feed>";
+        let xpath_map = pgrx::JsonB(json!({"creator": "//dc:creator"}));
+        assert_eq!(
+            _text_from_xml(Some(document), Some(xpath_map), None),
+            Some("creator: Ann".to_string())
+        );
+    }
+
+    #[pg_test]
+    fn test_text_from_null_xml() {
+        assert_eq!(_text_from_xml(None, Some(xpath_map()), None), None);
+        assert_eq!(_text_from_xml(Some(PRODUCT), None, Some("{{ name }}")), None);
+    }
+
+    #[pg_test(
+        error = "[rag] Invalid XPath for name: unexpected end of XPath: expected an element name, *, @attribute or text()"
+    )]
+    fn test_text_from_xml_invalid_xpath() {
+        _text_from_xml(Some(PRODUCT), Some(pgrx::JsonB(json!({"name": "//product/"}))), None);
+    }
+
+    #[pg_test(error = "[rag] Invalid XPath for count: expected / or // at character 1")]
+    fn test_text_from_xml_unsupported_xpath() {
+        _text_from_xml(Some(PRODUCT), Some(pgrx::JsonB(json!({"count": "count(//tag)"}))), None);
+    }
+
+    #[pg_test(error = "[rag] Invalid XML: expected '?>' at 1:6")]
+    fn test_text_from_not_xml() {
+        _text_from_xml(Some("<?xml"), Some(xpath_map()), None);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::errors::*;
+use pgrx::prelude::*;
+
+struct Cue {
+    start_ms: i64,
+    end_ms: i64,
+    speaker: Option<String>,
+    text: String,
+}
+
+// accepts both SRT (00:01:02,500) and WebVTT (00:01:02.500 or 01:02.500) timestamps
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let (clock, fraction) = timestamp.split_once([',', '.']).unwrap_or((timestamp, "0"));
+    if fraction.is_empty() || fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let ms = fraction.parse::<i64>().ok()? * 10_i64.pow(3 - fraction.len() as u32);
+    let parts = clock
+        .split(':')
+        .map(|part| match part.chars().all(|c| c.is_ascii_digit()) {
+            true => part.parse::<i64>().ok(),
+            false => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        [minutes, seconds] => (0, minutes, seconds),
+        _ => return None,
+    };
+    if minutes > 59 || seconds > 59 {
+        return None;
+    }
+    // hours can have any number of digits
+    hours
+        .checked_mul(60)?
+        .checked_add(minutes)?
+        .checked_mul(60)?
+        .checked_add(seconds)?
+        .checked_mul(1000)?
+        .checked_add(ms)
+}
+
+// WebVTT cue settings (e.g. "align:start") may follow the end time
+fn parse_timing(line: &str) -> (i64, i64) {
+    let (start, rest) = line.split_once("-->").unwrap_or_default();
+    let end = rest.split_whitespace().next().unwrap_or_default();
+    match (parse_timestamp(start.trim()), parse_timestamp(end)) {
+        (Some(start_ms), Some(end_ms)) if end_ms >= start_ms => (start_ms, end_ms),
+        (Some(_), Some(_)) => error!("{ERR_PREFIX} Subtitle cue ends before it starts: {}", line.trim()),
+        _ => error!("{ERR_PREFIX} Invalid subtitle timing: {}", line.trim()),
+    }
+}
+
+// Drops WebVTT tags (e.g. <i>, <c.yellow>, <00:00:01.000>) and SSA-style overrides found in SRT files (e.g. {\an8}),
+// and decodes the character references WebVTT allows. Returns the speaker given by the first voice tag (<v Name>).
+fn strip_tags(line: &str) -> (String, Option<String>) {
+    let mut text = String::new();
+    let mut speaker = None;
+    let mut rest = line;
+    while let Some(start) = rest.find(['<', '{']) {
+        text.push_str(&rest[..start]);
+        let close = match &rest[start..] {
+            tag if tag.starts_with('<') => '>',
+            tag if tag.starts_with("{\\") => '}',
+            _ => {
+                text.push('{');
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+        let Some(len) = rest[start..].find(close) else {
+            text.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + len];
+        if let Some(annotation) = tag.strip_prefix('v').filter(|a| a.starts_with([' ', '.'])) {
+            if let Some((_, name)) = annotation.split_once(' ') {
+                speaker = speaker.or(Some(name.trim().to_string()));
+            }
+        }
+        rest = &rest[start + len + 1..];
+    }
+    text.push_str(rest);
+    let text = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&lrm;", "")
+        .replace("&rlm;", "")
+        .replace("&amp;", "&");
+    (text, speaker.filter(|name| !name.is_empty()))
+}
+
+// A "Name:" label, as many transcription tools put at the start of each cue: up to four capitalized words
+fn speaker_label(text: &str) -> Option<(String, String)> {
+    let (label, rest) = text.split_once(':')?;
+    let words: Vec<&str> = label.split_whitespace().collect();
+    let is_name = !words.is_empty()
+        && words.len() <= 4
+        && words.iter().all(|word| {
+            word.starts_with(char::is_uppercase)
+                && word.chars().all(|c| c.is_alphabetic() || matches!(c, '.' | '-' | '\''))
+        });
+    (is_name && rest.starts_with(' ')).then(|| (words.join(" "), rest.trim().to_string()))
+}
+
+// Cues are blocks of lines, separated by blank lines, that contain a timing line: any identifier or sequence number
+// before it is ignored, and the lines after it are the cue's text. Other blocks (the WebVTT header, NOTE, STYLE and
+// REGION blocks) are skipped, as are cues without any text.
+fn parse_cues(document: &str) -> Vec<Cue> {
+    let document = document
+        .trim_start_matches('\u{feff}')
+        .replace("\r\n", "\n")
+        .replace('\r', "\n");
+    let mut cues = vec![];
+    let mut block: Vec<&str> = vec![];
+    for line in document.split('\n').chain([""]) {
+        if !line.trim().is_empty() {
+            block.push(line);
+            continue;
+        }
+        if let Some(timing) = block.iter().position(|line| line.contains("-->")) {
+            let (start_ms, end_ms) = parse_timing(block[timing]);
+            let mut speaker = None;
+            let mut lines = vec![];
+            for line in &block[timing + 1..] {
+                let (text, voice) = strip_tags(line);
+                speaker = speaker.or(voice);
+                lines.push(text.split_whitespace().collect::<Vec<_>>().join(" "));
+            }
+            let mut text = lines.join(" ").trim().to_string();
+            if speaker.is_none() {
+                if let Some((label, rest)) = speaker_label(&text) {
+                    speaker = Some(label);
+                    text = rest;
+                }
+            }
+            if !text.is_empty() {
+                cues.push(Cue {
+                    start_ms,
+                    end_ms,
+                    speaker,
+                    text,
+                });
+            }
+        }
+        block.clear();
+    }
+    cues
+}
+
+// Each chunk starts with a cue and takes in the cues that follow until it would last longer than max_duration_ms. A
+// cue that's longer than that on its own gets a chunk to itself. Speakers are named at the start of each chunk and
+// whenever they change.
+fn chunk_cues(cues: Vec<Cue>, max_duration_ms: i64) -> Vec<(i64, i64, String)> {
+    let mut chunks: Vec<(i64, i64, String)> = vec![];
+    let mut speaker = None;
+    for cue in cues {
+        let fits = chunks
+            .last()
+            .is_some_and(|(start_ms, _, _)| cue.end_ms - start_ms <= max_duration_ms);
+        if !fits {
+            speaker = None;
+        }
+        let named = cue.speaker.is_some() && cue.speaker != speaker;
+        let text = match &cue.speaker {
+            Some(name) if named => format!("{name}: {}", cue.text),
+            _ => cue.text,
+        };
+        match chunks.last_mut() {
+            Some((_, end_ms, chunk)) if fits => {
+                *end_ms = (*end_ms).max(cue.end_ms);
+                chunk.push(if named { '\n' } else { ' ' });
+                chunk.push_str(&text);
+            }
+            _ => chunks.push((cue.start_ms, cue.end_ms, text)),
+        }
+        if cue.speaker.is_some() {
+            speaker = cue.speaker;
+        }
+    }
+    chunks
+}
+
+#[pg_schema]
+mod rag {
+    use super::super::errors::*;
+    use super::{chunk_cues, parse_cues};
+    use pgrx::prelude::*;
+
+    #[allow(clippy::type_complexity)]
+    #[pg_extern(immutable, strict)]
+    pub fn cues_from_subtitles(
+        document: &str,
+    ) -> TableIterator<
+        'static,
+        (
+            name!(start_ms, i64),
+            name!(end_ms, i64),
+            name!(speaker, Option<String>),
+            name!(text, String),
+        ),
+    > {
+        TableIterator::new(
+            parse_cues(document)
+                .into_iter()
+                .map(|cue| (cue.start_ms, cue.end_ms, cue.speaker, cue.text)),
+        )
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn cue_chunks_by_duration(
+        document: &str,
+        max_duration_ms: i64,
+    ) -> TableIterator<'static, (name!(start_ms, i64), name!(end_ms, i64), name!(text, String))> {
+        if max_duration_ms < 1 {
+            error!("{ERR_PREFIX} max_duration_ms must be >= 1");
+        }
+        TableIterator::new(chunk_cues(parse_cues(document), max_duration_ms))
+    }
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+
+    const SRT: &str =
+        "1\r\n00:00:01,000 --> 00:00:04,500\r\n<i>Alice:</i> Welcome, everyone,\r\nto the meeting.\r\n\r\n\
+        2\r\n00:00:05,000 --> 00:00:07,250\r\n{\\an8}Thanks, Alice.\r\n\r\n\
+        3\r\n01:02:03,004 --> 01:02:04,000\r\nBob Smith: Bye &amp; thanks!\r\n";
+
+    const VTT: &str = "WEBVTT - Weekly sync\n\nNOTE recorded automatically\n\n\
+        intro\n00:01.000 --> 00:04.500 align:start\n<v.loud Alice Jones>Welcome, <b>everyone</b>.</v>\n\n\
+        00:05.000 --> 00:07.250\n<v Bob>Thanks.\n\n\
+        00:00:08.000 --> 00:00:10.000\nNo speaker here\nover two lines\n\n\
+        00:00:11.000 --> 00:00:12.000\n<c.music>\u{266a}</c>\n\n\
+        00:00:13.000 --> 00:00:14.000\n\n";
+
+    fn cues(document: &str) -> Vec<(i64, i64, Option<String>, String)> {
+        cues_from_subtitles(document).collect()
+    }
+
+    #[pg_test]
+    fn test_cues_from_srt() {
+        assert_eq!(
+            cues(SRT),
+            vec![
+                (
+                    1000,
+                    4500,
+                    Some("Alice".to_string()),
+                    "Welcome, everyone, to the meeting.".to_string()
+                ),
+                (5000, 7250, None, "Thanks, Alice.".to_string()),
+                (
+                    3723004,
+                    3724000,
+                    Some("Bob Smith".to_string()),
+                    "Bye & thanks!".to_string()
+                ),
+            ]
+        )
+    }
+
+    #[pg_test]
+    fn test_cues_from_vtt() {
+        assert_eq!(
+            cues(VTT),
+            vec![
+                (
+                    1000,
+                    4500,
+                    Some("Alice Jones".to_string()),
+                    "Welcome, everyone.".to_string()
+                ),
+                (5000, 7250, Some("Bob".to_string()), "Thanks.".to_string()),
+                (8000, 10000, None, "No speaker here over two lines".to_string()),
+                (11000, 12000, None, "\u{266a}".to_string()),
+            ]
+        )
+    }
+
+    #[pg_test(error = "[rag] Invalid subtitle timing: 00:00:01 --> soon")]
+    fn test_cues_from_invalid_subtitles() {
+        cues("1\n00:00:01 --> soon\nText\n");
+    }
+
+    #[pg_test(error = "[rag] Invalid subtitle timing: 9999999999999999:00:00 --> 9999999999999999:00:01")]
+    fn test_cues_from_subtitles_with_huge_timestamps() {
+        cues("1\n9999999999999999:00:00 --> 9999999999999999:00:01\nText\n");
+    }
+
+    #[pg_test(error = "[rag] Subtitle cue ends before it starts: 00:00:05,000 --> 00:00:01,000")]
+    fn test_cues_from_subtitles_ending_before_start() {
+        cues("1\n00:00:05,000 --> 00:00:01,000\nText\n");
+    }
+
+    #[pg_test]
+    fn test_cue_chunks_by_duration() {
+        assert_eq!(
+            cue_chunks_by_duration(VTT, 7000).collect::<Vec<_>>(),
+            vec![
+                (1000, 7250, "Alice Jones: Welcome, everyone.\nBob: Thanks.".to_string()),
+                (8000, 12000, "No speaker here over two lines \u{266a}".to_string()),
+            ]
+        );
+        assert_eq!(
+            cue_chunks_by_duration(SRT, 10000).collect::<Vec<_>>(),
+            vec![
+                (
+                    1000,
+                    7250,
+                    "Alice: Welcome, everyone, to the meeting. Thanks, Alice.".to_string()
+                ),
+                (3723004, 3724000, "Bob Smith: Bye & thanks!".to_string()),
+            ]
+        );
+        let same_speaker = "WEBVTT\n\n00:01.000 --> 00:02.000\n<v Alice>One.\n\n00:03.000 --> 00:04.000\n<v Alice>Two.";
+        assert_eq!(
+            cue_chunks_by_duration(same_speaker, 1000).collect::<Vec<_>>(),
+            vec![
+                (1000, 2000, "Alice: One.".to_string()),
+                (3000, 4000, "Alice: Two.".to_string()),
+            ]
+        );
+        assert_eq!(
+            cue_chunks_by_duration(same_speaker, 3000).collect::<Vec<_>>(),
+            vec![(1000, 4000, "Alice: One. Two.".to_string())]
+        );
+    }
+
+    #[pg_test(error = "[rag] max_duration_ms must be >= 1")]
+    fn test_cue_chunks_by_zero_duration() {
+        cue_chunks_by_duration(SRT, 0);
+    }
+}
@@ -0,0 +1,679 @@
+// Optional background worker that extracts from documents in a separate process, so that a parser's memory spike,
+// panic or runaway loop doesn't happen in the user's backend. It's started only when rag is in
+// shared_preload_libraries. A thread can't be stopped, but a process can: when an extraction is abandoned, on a timeout
+// or because the backend gave up waiting, the worker exits once it has sent its replies, and Postgres restarts it.
+
+mod extraction {
+    tonic::include_proto!("extraction");
+}
+
+use crate::errors::*;
+use crate::limits::{self, Limits};
+use crate::{document, docx, email, epub, odt, pdf, pptx, xlsx};
+use extraction::{
+    extractor_client::ExtractorClient,
+    extractor_server::{Extractor as ExtractorService, ExtractorServer},
+    value::Kind,
+    Doubles, ExtractionReply, ExtractionRequest, Row, Value,
+};
+use hyper_util::rt::TokioIo;
+use pgrx::pg_sys::panic::ErrorReportWithLevel;
+use pgrx::{bgworkers::*, prelude::*};
+use std::any::Any;
+use std::future::Future;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fs, sync::OnceLock};
+use tokio::{
+    net::{UnixListener, UnixStream},
+    sync::oneshot,
+    time::{sleep, timeout, Duration, Instant},
+};
+use tokio_stream::{wrappers::UnixListenerStream, StreamExt};
+use tonic::transport::{Endpoint, Server, Uri};
+use tonic::{Code, Request, Response, Status};
+use tower::service_fn;
+
+// the postmaster's PID, which backends inherit: it's only set if the worker has been registered
+static PID: OnceLock<i64> = OnceLock::new();
+
+// set in the worker when an extraction has been abandoned but may still be running
+static RECYCLE: AtomicBool = AtomicBool::new(false);
+
+// how long a backend keeps trying to connect, which covers the worker being restarted
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn socket_path(pid: i64) -> String {
+    format!("/tmp/.s.pgrag.rag.{pid}")
+}
+
+pub fn register() {
+    if unsafe { !pg_sys::process_shared_preload_libraries_in_progress } {
+        return; // loaded on demand by a backend, so extraction happens in-process
+    }
+    let pid = std::process::id() as i64;
+    PID.set(pid)
+        .expect_or_pg_err("Impossible concurrent access to set PID value");
+
+    BackgroundWorkerBuilder::new("rag extraction background worker")
+        .set_function("extraction_background_main")
+        .set_library("rag")
+        .set_argument(pid.into_datum())
+        .set_restart_time(Some(Duration::from_secs(1)))
+        .enable_shmem_access(None)
+        .load();
+}
+
+// only processes running as the same user as Postgres may connect, whichever end they're at
+fn same_user(stream: &UnixStream) -> bool {
+    stream
+        .peer_cred()
+        .is_ok_and(|credentials| credentials.uid() == unsafe { libc::geteuid() })
+}
+
+// results are sent as rows of values, which these traits convert to and from
+
+pub trait Field: Sized {
+    fn into_value(self) -> Value;
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+impl Field for String {
+    fn into_value(self) -> Value {
+        Value {
+            kind: Some(Kind::Text(self)),
+        }
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value.kind? {
+            Kind::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl Field for Vec<u8> {
+    fn into_value(self) -> Value {
+        Value {
+            kind: Some(Kind::Bytes(self)),
+        }
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value.kind? {
+            Kind::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+impl Field for i32 {
+    fn into_value(self) -> Value {
+        Value {
+            kind: Some(Kind::Integer(self)),
+        }
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value.kind? {
+            Kind::Integer(integer) => Some(integer),
+            _ => None,
+        }
+    }
+}
+
+impl Field for Vec<f64> {
+    fn into_value(self) -> Value {
+        Value {
+            kind: Some(Kind::Doubles(Doubles { values: self })),
+        }
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value.kind? {
+            Kind::Doubles(doubles) => Some(doubles.values),
+            _ => None,
+        }
+    }
+}
+
+impl Field for pgrx::JsonB {
+    fn into_value(self) -> Value {
+        Value {
+            kind: Some(Kind::Json(self.0.to_string())),
+        }
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value.kind? {
+            Kind::Json(json) => serde_json::from_str(&json).ok().map(pgrx::JsonB),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Field> Field for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value { kind: None }, T::into_value)
+    }
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value.kind {
+            None => Some(None),
+            Some(_) => T::from_value(value).map(Some),
+        }
+    }
+}
+
+pub trait Record: Sized {
+    fn into_row(self) -> Row;
+    fn from_row(row: Row) -> Option<Self>;
+}
+
+macro_rules! impl_record {
+    ($($field:ident $value:ident),+) => {
+        impl<$($field: Field),+> Record for ($($field,)+) {
+            fn into_row(self) -> Row {
+                let ($($value,)+) = self;
+                Row { values: vec![$($value.into_value()),+] }
+            }
+
+            fn from_row(row: Row) -> Option<Self> {
+                let mut values = row.values.into_iter();
+                Some(($($field::from_value(values.next()?)?,)+))
+            }
+        }
+    };
+}
+
+impl_record!(A a);
+impl_record!(A a, B b);
+impl_record!(A a, B b, C c);
+impl_record!(A a, B b, C c, D d);
+impl_record!(A a, B b, C c, D d, E e);
+impl_record!(A a, B b, C c, D d, E e, F f);
+
+fn rows<R: Record>(records: Vec<R>) -> Vec<Row> {
+    records.into_iter().map(Record::into_row).collect()
+}
+
+fn value<T: Field>(value: T) -> Vec<Row> {
+    vec![(value,).into_row()]
+}
+
+fn argument(arguments: &[String], index: usize) -> Option<&str> {
+    arguments.get(index).map(String::as_str)
+}
+
+type Extractor = fn(Vec<u8>, &[String]) -> Vec<Row>;
+
+// everything that's extracted from a document, by the name of the SQL function that does it
+const EXTRACTORS: &[(&str, Extractor)] = &[
+    ("text_from_pdf", |document, arguments| {
+        value(pdf::rag::pdf_text(&document, argument(arguments, 0)))
+    }),
+    ("pages_from_pdf", |document, _| rows(pdf::rag::pdf_pages(&document))),
+    ("blocks_from_pdf", |document, _| rows(pdf::rag::pdf_blocks(&document))),
+    ("images_from_pdf", |document, _| rows(pdf::rag::pdf_images(&document))),
+    ("metadata_from_pdf", |document, _| {
+        value(pdf::rag::pdf_metadata(&document))
+    }),
+    ("text_from_docx", |document, _| value(docx::rag::docx_text(document))),
+    ("text_from_docx_parts", |document, _| {
+        value(docx::rag::docx_parts(document))
+    }),
+    ("markdown_from_docx", |document, _| {
+        value(docx::rag::docx_markdown(document))
+    }),
+    ("images_from_docx", |document, _| rows(docx::rag::docx_images(document))),
+    ("text_from_pptx", |document, _| rows(pptx::rag::pptx_slides(document))),
+    ("markdown_from_xlsx", |document, _| {
+        value(xlsx::rag::xlsx_markdown(document))
+    }),
+    ("rows_from_xlsx", |document, _| rows(xlsx::rag::xlsx_rows(document))),
+    ("text_from_epub", |document, _| rows(epub::rag::epub_chapters(document))),
+    ("text_from_odt", |document, _| value(odt::rag::odt_text(document))),
+    ("parse_email", |document, _| value(email::rag::email_fields(&document))),
+    ("email_attachments", |document, _| {
+        rows(email::rag::email_attachment_rows(&document))
+    }),
+    ("emails_from_mbox", |document, _| {
+        rows(email::rag::mbox_messages(&document))
+    }),
+    ("text_from_document", |document, arguments| {
+        value(document::document_text(&document, argument(arguments, 0)))
+    }),
+    ("documents_from_zip", |document, _| {
+        rows(document::zip_documents(&document))
+    }),
+];
+
+fn run_extractor(function: &str, document: Vec<u8>, arguments: &[String]) -> Vec<Row> {
+    let (_, extractor) = EXTRACTORS
+        .iter()
+        .find(|(name, _)| *name == function)
+        .unwrap_or_pg_err(&format!("Unknown extraction function: {function}"));
+    extractor(document, arguments)
+}
+
+// Extracts from a document with the named extractor (see EXTRACTORS) in the worker, if there is one, and otherwise
+// in-process on an extraction thread. On an extraction thread already (as when text_from_document calls text_from_pdf,
+// or in the worker), the extractor is simply called.
+pub fn extract<R: Record>(function: &'static str, document: Vec<u8>, arguments: &[&str]) -> Vec<R> {
+    let arguments: Vec<String> = arguments.iter().map(|argument| argument.to_string()).collect();
+    let rows = if limits::on_extraction_thread() {
+        run_extractor(function, document, &arguments)
+    } else {
+        limits::check_document_size(&document); // no need to go further if it's too big
+        match PID.get() {
+            Some(&pid) => extract_in_worker(pid, function, document, arguments),
+            None => limits::run_extraction(move || run_extractor(function, document, &arguments)),
+        }
+    };
+    rows.into_iter()
+        .map(|row| R::from_row(row).unwrap_or_pg_err("Unexpected result from extraction"))
+        .collect()
+}
+
+// for a function that returns a single value
+pub fn extract_value<T: Field>(function: &'static str, document: Vec<u8>, arguments: &[&str]) -> T {
+    let (value,) = extract::<(T,)>(function, document, arguments)
+        .pop()
+        .unwrap_or_pg_err("Unexpected result from extraction");
+    value
+}
+
+fn extract_in_worker(pid: i64, function: &str, document: Vec<u8>, arguments: Vec<String>) -> Vec<Row> {
+    let limits = limits::session_limits();
+    let request = ExtractionRequest {
+        function: function.to_string(),
+        document,
+        arguments,
+        max_document_bytes: limits.max_document_bytes,
+        max_pdf_pages: limits.max_pdf_pages,
+        max_zip_entries: limits.max_zip_entries,
+        max_decompressed_bytes: limits.max_decompressed_bytes,
+    };
+    // waiting on an extraction thread keeps us responsive to query cancellation, and applies rag.extraction_timeout
+    match limits::run_extraction(move || request_extraction(&socket_path(pid), request)) {
+        Ok(rows) => rows,
+        Err(status) => match status.code() {
+            Code::ResourceExhausted => {
+                ereport!(
+                    ERROR,
+                    PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED,
+                    status.message().to_string()
+                );
+            }
+            Code::InvalidArgument => error!("{}", status.message()),
+            _ => error!("{ERR_PREFIX} Extraction worker failed: {}", status.message()),
+        },
+    }
+}
+
+// background worker
+
+// what the worker reads from its settings, which can only be done on its main thread
+#[derive(Clone, Copy)]
+struct WorkerSettings {
+    limits: Limits,
+    extraction_timeout_ms: i32,
+}
+
+fn worker_settings() -> WorkerSettings {
+    WorkerSettings {
+        limits: limits::session_limits(),
+        extraction_timeout_ms: limits::EXTRACTION_TIMEOUT.get(),
+    }
+}
+
+struct ExtractorStruct {
+    settings: WorkerSettings,
+}
+
+// the extractors raise errors as panics, which carry the message and SQLSTATE back to the backend
+fn status_from_panic(payload: Box<dyn Any + Send>) -> Status {
+    match payload.downcast::<ErrorReportWithLevel>() {
+        Ok(report) => match report.sql_error_code() {
+            PgSqlErrorCode::ERRCODE_PROGRAM_LIMIT_EXCEEDED => Status::resource_exhausted(report.message()),
+            _ => Status::invalid_argument(report.message()),
+        },
+        Err(payload) => Status::internal(limits::panic_message(payload.as_ref())),
+    }
+}
+
+// Marks the worker for recycling if dropped while armed: tonic drops a request's future if the client goes away, and
+// the extraction thread is still running then
+struct AbandonGuard {
+    armed: bool,
+}
+
+impl Drop for AbandonGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            RECYCLE.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ExtractorService for ExtractorStruct {
+    async fn extract(&self, request: Request<ExtractionRequest>) -> Result<Response<ExtractionReply>, Status> {
+        let request = request.into_inner();
+        let settings = self.settings;
+        let limits = settings.limits.stricter(Limits {
+            max_document_bytes: request.max_document_bytes,
+            max_pdf_pages: request.max_pdf_pages,
+            max_zip_entries: request.max_zip_entries,
+            max_decompressed_bytes: request.max_decompressed_bytes,
+        });
+
+        let mut guard = AbandonGuard { armed: true };
+        let (sender, receiver) = oneshot::channel();
+        limits::spawn_extraction_thread(move || {
+            limits::set_extraction_limits(limits);
+            let ExtractionRequest {
+                function,
+                document,
+                arguments,
+                ..
+            } = request;
+            let result = std::panic::catch_unwind(move || run_extractor(&function, document, &arguments));
+            let _ = sender.send(result); // the receiver is gone if the extraction was abandoned
+        })
+        .map_err(|err| Status::internal(format!("Couldn't start extraction thread: {err}")))?;
+
+        let timeout_ms = settings.extraction_timeout_ms;
+        let result = match timeout_ms {
+            0 => receiver.await,
+            _ => match timeout(Duration::from_millis(timeout_ms as u64), receiver).await {
+                Ok(result) => result,
+                Err(_) => {
+                    return Err(Status::resource_exhausted(format!(
+                        "{ERR_PREFIX} Extraction took too long (rag.extraction_timeout is {timeout_ms} ms)"
+                    )));
+                }
+            },
+        };
+        guard.armed = false;
+        match result {
+            Err(_) => Err(Status::internal("Extraction thread exited unexpectedly")),
+            Ok(Err(payload)) => Err(status_from_panic(payload)),
+            Ok(Ok(rows)) => Ok(Response::new(ExtractionReply { rows })),
+        }
+    }
+}
+
+// Serves requests until shutdown completes, or until an extraction is abandoned: then it stops accepting connections
+// and returns once the replies in progress have been sent
+async fn serve(path: &str, settings: WorkerSettings, shutdown: impl Future<Output = ()>) {
+    fs::remove_file(path).unwrap_or_default(); // it's not an error if the file isn't there
+    let uds = UnixListener::bind(path).expect_or_pg_err(&format!("Couldn't create socket at {path}"));
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .expect_or_pg_err(&format!("Couldn't set permissions for {path}"));
+
+    let uds_stream = UnixListenerStream::new(uds).filter(|stream| stream.as_ref().map_or(true, same_user));
+    Server::builder()
+        .add_service(ExtractorServer::new(ExtractorStruct { settings }).max_decoding_message_size(usize::MAX))
+        .serve_with_incoming_shutdown(uds_stream, async {
+            tokio::select! {
+                _ = shutdown => (),
+                _ = async {
+                    while !RECYCLE.load(Ordering::SeqCst) {
+                        sleep(POLL_INTERVAL).await;
+                    }
+                } => (),
+            }
+        })
+        .await
+        .expect_or_pg_err("Couldn't create server");
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C-unwind" fn extraction_background_main(arg: pg_sys::Datum) {
+    let pid = unsafe { i64::from_polymorphic_datum(arg, false, pg_sys::INT8OID).unwrap_or_pg_err("No PID received") };
+    let name = BackgroundWorker::get_name();
+    log!("{ERR_PREFIX} {name} started, received PID {pid}");
+
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+    let path = socket_path(pid);
+    loop {
+        // settings are read here, on the main thread, and again after a configuration reload
+        let settings = worker_settings();
+        let mut reload = false;
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect_or_pg_err("Couldn't build tokio runtime for server")
+            .block_on(serve(&path, settings, async {
+                // wait_latch is not an async function and does not suspend
+                while BackgroundWorker::wait_latch(Some(Duration::from_secs(0))) {
+                    if BackgroundWorker::sighup_received() {
+                        reload = true;
+                        break;
+                    }
+                    // suspend so that other asyncs/threads can run
+                    sleep(Duration::from_millis(500)).await;
+                }
+            }));
+
+        if RECYCLE.load(Ordering::SeqCst) {
+            log!("{ERR_PREFIX} {name} is restarting to stop an abandoned extraction");
+            unsafe { pg_sys::proc_exit(1) }; // nonzero, so that Postgres restarts us
+        }
+        if !reload {
+            break;
+        }
+        unsafe { pg_sys::ProcessConfigFile(pg_sys::GucContext::PGC_SIGHUP) };
+    }
+}
+
+// client
+
+// Runs on an extraction thread, so mustn't call into Postgres. If the backend stops waiting, this drops the request,
+// which makes the worker stop the extraction.
+fn request_extraction(path: &str, request: ExtractionRequest) -> Result<Vec<Row>, Box<Status>> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| Status::internal(format!("Couldn't build tokio runtime for client: {err}")))?
+        .block_on(async {
+            let endpoint =
+                Endpoint::try_from("http://[::]:80") // URL must be valid but is ignored
+                    .map_err(|err| Status::internal(format!("Failed to create endpoint: {err}")))?;
+            let path = path.to_string();
+            let connector = service_fn(move |_: Uri| {
+                let path = path.clone();
+                async move {
+                    let stream = UnixStream::connect(path).await?;
+                    match same_user(&stream) {
+                        true => Ok::<_, std::io::Error>(TokioIo::new(stream)),
+                        false => Err(std::io::Error::other("worker socket belongs to another user")),
+                    }
+                }
+            });
+
+            // the worker may be restarting
+            let deadline = Instant::now() + CONNECT_TIMEOUT;
+            let channel = loop {
+                match endpoint.connect_with_connector(connector.clone()).await {
+                    Ok(channel) => break channel,
+                    Err(_) if Instant::now() < deadline => sleep(Duration::from_millis(200)).await,
+                    Err(err) => return Err(Status::unavailable(format!("Couldn't connect worker channel: {err}"))),
+                }
+            };
+
+            let mut client = ExtractorClient::new(channel).max_decoding_message_size(usize::MAX);
+            tokio::select! {
+                response = client.extract(tonic::Request::new(request)) => Ok(response?.into_inner().rows),
+                _ = async {
+                    while !limits::extraction_abandoned() {
+                        sleep(POLL_INTERVAL).await;
+                    }
+                } => Err(Status::cancelled("Extraction abandoned")),
+            }
+        })
+        .map_err(Box::new)
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn test_settings(limits: Limits) -> WorkerSettings {
+        WorkerSettings {
+            limits,
+            extraction_timeout_ms: 0,
+        }
+    }
+
+    fn no_limits() -> Limits {
+        Limits {
+            max_document_bytes: 0,
+            max_pdf_pages: 0,
+            max_zip_entries: 0,
+            max_decompressed_bytes: 0,
+        }
+    }
+
+    fn request(function: &str, document: &[u8], limits: Limits) -> ExtractionRequest {
+        ExtractionRequest {
+            function: function.to_string(),
+            document: document.to_vec(),
+            arguments: vec![],
+            max_document_bytes: limits.max_document_bytes,
+            max_pdf_pages: limits.max_pdf_pages,
+            max_zip_entries: limits.max_zip_entries,
+            max_decompressed_bytes: limits.max_decompressed_bytes,
+        }
+    }
+
+    // runs a server on its own thread for the duration of the requests, which retry until it's listening
+    fn with_server<T>(settings: WorkerSettings, requests: impl FnOnce(&str) -> T) -> T {
+        static SERVERS: AtomicUsize = AtomicUsize::new(0);
+        let server_number = SERVERS.fetch_add(1, Ordering::SeqCst);
+        let path = format!("/tmp/.s.pgrag.rag.test.{}.{server_number}", std::process::id());
+        let (shutdown, shutdown_received) = oneshot::channel::<()>();
+        let server = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap()
+                    .block_on(serve(&path, settings, async {
+                        let _ = shutdown_received.await;
+                    }))
+            })
+        };
+        let result = requests(&path);
+        shutdown.send(()).unwrap();
+        server.join().unwrap();
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[pg_test]
+    fn test_request_extraction() {
+        let rows = with_server(test_settings(no_limits()), |path| {
+            request_extraction(
+                path,
+                request("text_from_pdf", include_bytes!("../test_res/test.pdf"), no_limits()),
+            )
+        })
+        .unwrap();
+        let (text,) = <(String,)>::from_row(rows.into_iter().next().unwrap()).unwrap();
+        assert!(text.contains("Test PDF document"));
+    }
+
+    #[pg_test]
+    fn test_request_extraction_error() {
+        let status = with_server(test_settings(no_limits()), |path| {
+            request_extraction(
+                path,
+                request("text_from_pdf", include_bytes!("../test_res/test.pages"), no_limits()),
+            )
+        })
+        .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "[rag] Error extracting text from PDF: PDF error: Invalid file header"
+        );
+    }
+
+    #[pg_test]
+    fn test_request_extraction_applies_stricter_limits() {
+        let strict = Limits {
+            max_document_bytes: 1000,
+            ..no_limits()
+        };
+        let document = include_bytes!("../test_res/test.pdf");
+        // the worker's own limits apply whatever the request says
+        let status = with_server(test_settings(strict), |path| {
+            request_extraction(path, request("text_from_pdf", document, no_limits()))
+        })
+        .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.message().starts_with("[rag] Document is too large"));
+        // and so do the request's, if they're stricter
+        let status = with_server(test_settings(no_limits()), |path| {
+            request_extraction(path, request("text_from_pdf", document, strict))
+        })
+        .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[pg_test]
+    fn test_status_from_panic() {
+        let status = |f: fn()| status_from_panic(std::panic::catch_unwind(f).unwrap_err());
+        let limit = status(|| limits::limit_exceeded("Too much"));
+        assert_eq!(
+            (limit.code(), limit.message()),
+            (Code::ResourceExhausted, "[rag] Too much")
+        );
+        let invalid = status(|| error!("[rag] Bad document"));
+        assert_eq!(
+            (invalid.code(), invalid.message()),
+            (Code::InvalidArgument, "[rag] Bad document")
+        );
+        let internal = status(|| panic!("oops"));
+        assert_eq!((internal.code(), internal.message()), (Code::Internal, "oops"));
+    }
+
+    #[pg_test]
+    fn test_record() {
+        let record = (
+            1,
+            Some("text".to_string()),
+            None::<String>,
+            vec![1.5, 2.5],
+            vec![0u8, 255],
+        );
+        assert_eq!(
+            <(i32, Option<String>, Option<String>, Vec<f64>, Vec<u8>)>::from_row(record.clone().into_row()),
+            Some(record)
+        );
+        assert_eq!(<(i32, String)>::from_row((1,).into_row()), None);
+    }
+
+    // with no worker, as in these tests, extraction happens in-process
+    #[pg_test]
+    fn test_extract_without_worker() {
+        assert!(PID.get().is_none());
+        let pages: Vec<(i32, String)> = extract("pages_from_pdf", include_bytes!("../test_res/test.pdf").to_vec(), &[]);
+        assert_eq!(pages.len(), 1);
+    }
+
+    #[pg_test(error = "[rag] Unknown extraction function: text_from_nothing")]
+    fn test_extract_unknown_function() {
+        extract_value::<String>("text_from_nothing", vec![], &[]);
+    }
+}
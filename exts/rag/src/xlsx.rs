@@ -0,0 +1,329 @@
+use pgrx::prelude::*;
+
+#[pg_schema]
+pub(crate) mod rag {
+    use super::super::document::{sniff_mime_type, MIME_TEXT};
+    use super::super::errors::*;
+    use super::super::limits::{check_document_size, check_zip_document};
+    use super::super::worker;
+    use calamine::{open_workbook_auto_from_rs, Cell, Data, Range, Reader};
+    use pgrx::prelude::*;
+    use serde_json::{Map, Value};
+    use std::collections::HashSet;
+    use std::io::Cursor;
+
+    // a CSV file has a single, unnamed sheet: this is what spreadsheet programs call it
+    const CSV_SHEET_NAME: &str = "Sheet1";
+
+    // the delimiter is whichever of comma, semicolon and tab appears most often in the first line
+    fn csv_delimiter(text: &str) -> u8 {
+        let first_line = text.lines().next().unwrap_or_default();
+        [b',', b';', b'\t']
+            .into_iter()
+            .rev()
+            .max_by_key(|delimiter| first_line.bytes().filter(|byte| byte == delimiter).count())
+            .unwrap_or(b',')
+    }
+
+    // as in spreadsheet programs, text with leading zeros (such as 007) is kept as text, as is "inf", "NaN", etc.
+    fn is_number(field: &str) -> bool {
+        let digits = field.trim_start_matches(['-', '+']).as_bytes();
+        let leading_zero = digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit();
+        !leading_zero
+            && field
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || b".eE+-".contains(&byte))
+    }
+
+    fn csv_cell(field: &str) -> Data {
+        let field = field.trim();
+        if field.is_empty() {
+            return Data::Empty;
+        }
+        if is_number(field) {
+            if let Ok(value) = field.parse::<i64>() {
+                return Data::Int(value);
+            }
+            if let Ok(value) = field.parse::<f64>() {
+                return Data::Float(value);
+            }
+        }
+        match field.to_lowercase().as_str() {
+            "true" => Data::Bool(true),
+            "false" => Data::Bool(false),
+            _ => Data::String(field.to_string()),
+        }
+    }
+
+    fn csv_range(text: &str) -> Range<Data> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(csv_delimiter(text))
+            .from_reader(text.as_bytes());
+        let mut cells = Vec::new();
+        for (row, record) in reader.records().enumerate() {
+            let record = record.expect_or_pg_err("Couldn't read CSV");
+            for (column, field) in record.iter().enumerate() {
+                let value = csv_cell(field);
+                if value != Data::Empty {
+                    cells.push(Cell::new((row as u32, column as u32), value));
+                }
+            }
+        }
+        Range::from_sparse(cells)
+    }
+
+    // .xlsx, .xlsm, .xlsb, .xls and .ods are all detected automatically, and plain text is read as CSV
+    fn sheets(document: Vec<u8>) -> Vec<(String, Range<Data>)> {
+        check_document_size(&document);
+        if sniff_mime_type(&document) == Some(MIME_TEXT) {
+            let text = String::from_utf8_lossy(&document);
+            return vec![(
+                CSV_SHEET_NAME.to_string(),
+                csv_range(text.trim_start_matches('\u{feff}')),
+            )];
+        }
+        check_zip_document(&document); // .xlsx and .ods files are zip-based, and others are ignored
+        let mut workbook =
+            open_workbook_auto_from_rs(Cursor::new(document)).expect_or_pg_err("Couldn't read spreadsheet");
+        workbook
+            .sheet_names()
+            .into_iter()
+            .map(|name| {
+                let range = workbook
+                    .worksheet_range(&name)
+                    .expect_or_pg_err(&format!("Couldn't read sheet '{name}'"));
+                (name, range)
+            })
+            .collect()
+    }
+
+    // returns the 1-based row number of each row that has at least one non-empty cell, along with its cells
+    fn non_empty_rows(range: &Range<Data>) -> Vec<(i32, &[Data])> {
+        let first_row = range.start().map(|(row, _)| row).unwrap_or(0);
+        range
+            .rows()
+            .enumerate()
+            .filter(|(_, cells)| cells.iter().any(|cell| !cell_text(cell).trim().is_empty()))
+            .map(|(index, cells)| (first_row as i32 + index as i32 + 1, cells))
+            .collect()
+    }
+
+    fn cell_text(cell: &Data) -> String {
+        match cell {
+            Data::DateTime(datetime) if datetime.is_datetime() => {
+                let (year, month, day, hour, minute, second, milli) = datetime.to_ymd_hms_milli();
+                match (hour, minute, second, milli) {
+                    (0, 0, 0, 0) => format!("{year:04}-{month:02}-{day:02}"),
+                    _ => format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}"),
+                }
+            }
+            Data::DateTime(duration) => {
+                let seconds = (duration.as_f64() * 86_400.0).round() as i64;
+                format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+            }
+            other => other.to_string(),
+        }
+    }
+
+    fn cell_value(cell: &Data) -> Value {
+        match cell {
+            Data::Empty => Value::Null,
+            Data::Bool(value) => Value::from(*value),
+            Data::Int(value) => Value::from(*value),
+            // whole numbers are stored as floats, but are more useful as integers
+            Data::Float(value) if value.fract() == 0.0 && value.abs() < i64::MAX as f64 => Value::from(*value as i64),
+            Data::Float(value) => Value::from(*value),
+            other => Value::from(cell_text(other)),
+        }
+    }
+
+    fn column_name(mut index: usize) -> String {
+        let mut name = String::new();
+        loop {
+            name.insert(0, (b'A' + (index % 26) as u8) as char);
+            if index < 26 {
+                return name;
+            }
+            index = index / 26 - 1;
+        }
+    }
+
+    // the first non-empty row supplies the keys, falling back to column letters for blanks and adding suffixes to
+    // duplicates
+    fn header_keys(range: &Range<Data>, header: &[Data]) -> Vec<String> {
+        let first_column = range.start().map(|(_, column)| column as usize).unwrap_or(0);
+        let mut seen = HashSet::new();
+        header
+            .iter()
+            .enumerate()
+            .map(|(index, cell)| {
+                let text = cell_text(cell).trim().to_string();
+                let key = match text.is_empty() {
+                    true => column_name(first_column + index),
+                    false => text,
+                };
+                let mut unique = key.clone();
+                let mut suffix = 1;
+                while !seen.insert(unique.clone()) {
+                    suffix += 1;
+                    unique = format!("{key}_{suffix}");
+                }
+                unique
+            })
+            .collect()
+    }
+
+    fn markdown_table(rows: &[(i32, &[Data])]) -> String {
+        let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+        let escape = |cell: &Data| {
+            cell_text(cell)
+                .trim()
+                .replace('|', "\\|")
+                .replace("\r\n", "<br>")
+                .replace('\n', "<br>")
+        };
+        let columns = rows[0].1.len();
+        let mut lines = vec![
+            line(rows[0].1.iter().map(escape).collect()),
+            line(vec!["---".to_string(); columns]),
+        ];
+        lines.extend(
+            rows[1..]
+                .iter()
+                .map(|(_, cells)| line(cells.iter().map(escape).collect())),
+        );
+        lines.join("\n")
+    }
+
+    pub(crate) fn xlsx_markdown(document: Vec<u8>) -> String {
+        sheets(document)
+            .iter()
+            .filter_map(|(name, range)| {
+                let rows = non_empty_rows(range);
+                match rows.is_empty() {
+                    true => None,
+                    false => Some(format!("# {name}\n\n{}", markdown_table(&rows))),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn markdown_from_xlsx(document: Vec<u8>) -> String {
+        worker::extract_value("markdown_from_xlsx", document, &[])
+    }
+
+    pub(crate) fn xlsx_rows(document: Vec<u8>) -> Vec<(String, i32, pgrx::JsonB)> {
+        let mut result = Vec::new();
+        for (name, range) in sheets(document) {
+            let rows = non_empty_rows(&range);
+            let Some(((_, header), records)) = rows.split_first() else {
+                continue;
+            };
+            let keys = header_keys(&range, header);
+            for (row_number, cells) in records {
+                let record: Map<String, Value> = keys.iter().cloned().zip(cells.iter().map(cell_value)).collect();
+                result.push((name.clone(), *row_number, pgrx::JsonB(Value::Object(record))));
+            }
+        }
+        result
+    }
+
+    #[pg_extern(immutable, strict)]
+    pub fn rows_from_xlsx(
+        document: Vec<u8>,
+    ) -> TableIterator<'static, (name!(sheet, String), name!(row_number, i32), name!(row, pgrx::JsonB))> {
+        TableIterator::new(worker::extract("rows_from_xlsx", document, &[]))
+    }
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pg_schema]
+mod tests {
+    use super::rag::*;
+    use pgrx::prelude::*;
+    use serde_json::json;
+
+    #[pg_test]
+    fn test_markdown_from_xlsx() {
+        assert_eq!(
+            markdown_from_xlsx(include_bytes!("../test_res/test.xlsx").to_vec()),
+            "# People\n\n| Name | Age | Joined | Active | Notes |\n| --- | --- | --- | --- | --- |\n| Ada | 36 | 2024-01-01 | true | likes a\\|b |\n| Bob | 41.5 |  | false | line one<br>line two |\n\n# Totals\n\n| Total |  |\n| --- | --- |\n|  | x |\n| 3 | 4 |"
+        );
+    }
+
+    #[pg_test]
+    fn test_markdown_from_ods() {
+        assert_eq!(
+            markdown_from_xlsx(include_bytes!("../test_res/test.ods").to_vec()),
+            "# Cities\n\n| City | Population |\n| --- | --- |\n| Oslo | 709037 |\n| Bergen | 291940 |"
+        );
+    }
+
+    #[pg_test]
+    fn test_rows_from_xlsx() {
+        let rows: Vec<_> = rows_from_xlsx(include_bytes!("../test_res/test.xlsx").to_vec())
+            .map(|(sheet, row_number, row)| (sheet, row_number, row.0))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    "People".to_string(),
+                    2,
+                    json!({"Name": "Ada", "Age": 36, "Joined": "2024-01-01", "Active": true, "Notes": "likes a|b"})
+                ),
+                (
+                    "People".to_string(),
+                    4,
+                    json!({"Name": "Bob", "Age": 41.5, "Joined": null, "Active": false, "Notes": "line one\nline two"})
+                ),
+                ("Totals".to_string(), 3, json!({"Total": "", "C": "x"})),
+                ("Totals".to_string(), 4, json!({"Total": 3, "C": 4})),
+            ]
+        );
+    }
+
+    const CSV: &str =
+        "\u{feff}Name;Code;Score;Passed\r\nAda;007;9.5;TRUE\r\n\"Lovelace; A.\";42;1.50;false\r\n\r\n;;;\r\nBob\r\n";
+
+    #[pg_test]
+    fn test_markdown_from_csv() {
+        assert_eq!(
+            markdown_from_xlsx(CSV.as_bytes().to_vec()),
+            "# Sheet1\n\n| Name | Code | Score | Passed |\n| --- | --- | --- | --- |\n| Ada | 007 | 9.5 | true |\n| Lovelace; A. | 42 | 1.5 | false |\n| Bob |  |  |  |"
+        );
+    }
+
+    #[pg_test]
+    fn test_rows_from_csv() {
+        let rows: Vec<_> = rows_from_xlsx(b"a,b\n1,x\t y\n".to_vec())
+            .map(|(sheet, row_number, row)| (sheet, row_number, row.0))
+            .collect();
+        assert_eq!(rows, vec![("Sheet1".to_string(), 2, json!({"a": 1, "b": "x\t y"}))]);
+        let rows: Vec<_> = rows_from_xlsx(CSV.as_bytes().to_vec())
+            .map(|(_, row_number, row)| (row_number, row.0))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (2, json!({"Name": "Ada", "Code": "007", "Score": 9.5, "Passed": true})),
+                (
+                    3,
+                    json!({"Name": "Lovelace; A.", "Code": 42, "Score": 1.5, "Passed": false})
+                ),
+                (5, json!({"Name": "Bob", "Code": null, "Score": null, "Passed": null})),
+            ]
+        );
+    }
+
+    #[pg_test(error = "[rag] Couldn't read spreadsheet: Cannot detect file format")]
+    fn test_markdown_from_not_xlsx() {
+        markdown_from_xlsx(include_bytes!("../test_res/test.pdf").to_vec());
+    }
+}
@@ -0,0 +1,423 @@
+// The XPaths in text_from_xml's xpath_map are a small subset of XPath 1.0: absolute location paths, whose steps select
+// child (/) or descendant (//) elements by name, optionally filtered by position or attribute, and which can end by
+// selecting an attribute or text nodes. For example:
+//
+//   /catalog/product[1]/name
+//   //p:price[@currency='EUR']
+//   //product[@sku]/@sku
+//   /doc//section/text()
+//
+// Evaluation takes time in proportion to the size of the document and the number of steps.
+
+use roxmltree::{Document, ExpandedName, Node};
+use std::collections::{HashMap, HashSet};
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+// (namespace URI, local name): as in XPath, names without a prefix are in no namespace
+type Name = (Option<String>, String);
+
+enum NameTest {
+    Any,
+    Name(Name),
+}
+
+impl NameTest {
+    fn matches(&self, name: ExpandedName) -> bool {
+        match self {
+            NameTest::Any => true,
+            NameTest::Name((namespace, local_name)) => {
+                name.namespace() == namespace.as_deref() && name.name() == local_name
+            }
+        }
+    }
+}
+
+enum Predicate {
+    Position(usize),
+    HasAttribute(Name),
+    AttributeEquals(Name, String),
+}
+
+impl Predicate {
+    fn attribute<'a>(node: &Node<'a, '_>, (namespace, local_name): &Name) -> Option<&'a str> {
+        match namespace {
+            Some(namespace) => node.attribute((namespace.as_str(), local_name.as_str())),
+            None => node.attribute(local_name.as_str()),
+        }
+    }
+
+    // positions count from 1 among the nodes that the previous predicates kept
+    fn filter<'a, 'input>(&self, nodes: Vec<Node<'a, 'input>>) -> Vec<Node<'a, 'input>> {
+        match self {
+            Predicate::Position(position) => nodes.into_iter().skip(position.wrapping_sub(1)).take(1).collect(),
+            Predicate::HasAttribute(name) => nodes
+                .into_iter()
+                .filter(|node| Self::attribute(node, name).is_some())
+                .collect(),
+            Predicate::AttributeEquals(name, value) => nodes
+                .into_iter()
+                .filter(|node| Self::attribute(node, name) == Some(value))
+                .collect(),
+        }
+    }
+}
+
+enum StepKind {
+    Elements(NameTest, Vec<Predicate>),
+    Attribute(NameTest),
+    Text,
+}
+
+struct Step {
+    descendant: bool,
+    kind: StepKind,
+}
+
+// Prefixes declared anywhere in the document, not just on the root element, can be used in XPaths. Where the same
+// prefix is declared more than once, the first declaration counts.
+pub fn namespaces(document: &Document) -> HashMap<String, String> {
+    let mut namespaces = HashMap::from([("xml".to_string(), XML_NAMESPACE.to_string())]);
+    for element in document.descendants().filter(Node::is_element) {
+        for namespace in element.namespaces() {
+            if let Some(prefix) = namespace.name() {
+                namespaces
+                    .entry(prefix.to_string())
+                    .or_insert_with(|| namespace.uri().to_string());
+            }
+        }
+    }
+    namespaces
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '\u{b7}')
+}
+
+struct Parser<'n> {
+    chars: Vec<char>,
+    at: usize,
+    namespaces: &'n HashMap<String, String>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.at == self.chars.len()
+    }
+
+    fn accept(&mut self, s: &str) -> bool {
+        let accepted = self.chars[self.at..].iter().take(s.len()).copied().eq(s.chars());
+        if accepted {
+            self.at += s.len();
+        }
+        accepted
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.accept(s) {
+            true => Ok(()),
+            false => Err(self.unexpected(s)),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> String {
+        match self.at_end() {
+            true => format!("unexpected end of XPath: expected {expected}"),
+            false => format!("expected {expected} at character {}", self.at + 1),
+        }
+    }
+
+    fn ncname(&mut self) -> Option<String> {
+        if !self.chars.get(self.at).is_some_and(|&c| is_name_start(c)) {
+            return None;
+        }
+        let start = self.at;
+        while self.chars.get(self.at).is_some_and(|&c| is_name_char(c)) {
+            self.at += 1;
+        }
+        Some(self.chars[start..self.at].iter().collect())
+    }
+
+    fn name(&mut self) -> Result<Name, String> {
+        let Some(name) = self.ncname() else {
+            return Err(self.unexpected("a name"));
+        };
+        if !self.accept(":") {
+            return Ok((None, name));
+        }
+        let Some(local_name) = self.ncname() else {
+            return Err(self.unexpected("a name"));
+        };
+        match self.namespaces.get(&name) {
+            Some(namespace) => Ok((Some(namespace.clone()), local_name)),
+            None => Err(format!("undeclared namespace prefix {name}")),
+        }
+    }
+
+    fn name_test(&mut self) -> Result<NameTest, String> {
+        match self.accept("*") {
+            true => Ok(NameTest::Any),
+            false => self.name().map(NameTest::Name),
+        }
+    }
+
+    fn literal(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let Some(&quote) = self.chars.get(self.at).filter(|&&c| c == '\'' || c == '"') else {
+            return Err(self.unexpected("a quoted value"));
+        };
+        let Some(len) = self.chars[self.at + 1..].iter().position(|&c| c == quote) else {
+            return Err("unterminated quoted value".to_string());
+        };
+        let literal = self.chars[self.at + 1..self.at + 1 + len].iter().collect();
+        self.at += len + 2;
+        Ok(literal)
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, String> {
+        self.skip_whitespace();
+        let start = self.at;
+        while self.chars.get(self.at).is_some_and(char::is_ascii_digit) {
+            self.at += 1;
+        }
+        let predicate = if self.at > start {
+            let digits: String = self.chars[start..self.at].iter().collect();
+            Predicate::Position(digits.parse().map_err(|_| format!("position {digits} is too large"))?)
+        } else if self.accept("@") {
+            let name = self.name()?;
+            self.skip_whitespace();
+            match self.accept("=") {
+                true => Predicate::AttributeEquals(name, self.literal()?),
+                false => Predicate::HasAttribute(name),
+            }
+        } else {
+            return Err(self.unexpected("a position, @name or @name='value'"));
+        };
+        self.expect("]")?;
+        Ok(predicate)
+    }
+
+    fn step(&mut self, descendant: bool) -> Result<Step, String> {
+        self.skip_whitespace();
+        let kind = if self.accept("@") {
+            StepKind::Attribute(self.name_test()?)
+        } else if self.accept("text()") {
+            StepKind::Text
+        } else {
+            let test = match self.chars.get(self.at).is_some_and(|&c| c == '*' || is_name_start(c)) {
+                true => self.name_test()?,
+                false => return Err(self.unexpected("an element name, *, @attribute or text()")),
+            };
+            let mut predicates = Vec::new();
+            loop {
+                self.skip_whitespace();
+                if !self.accept("[") {
+                    break;
+                }
+                predicates.push(self.predicate()?);
+            }
+            StepKind::Elements(test, predicates)
+        };
+        Ok(Step { descendant, kind })
+    }
+}
+
+pub struct XPath(Vec<Step>);
+
+impl XPath {
+    // namespaces maps the prefixes that can be used to their URIs
+    pub fn parse(xpath: &str, namespaces: &HashMap<String, String>) -> Result<XPath, String> {
+        let mut parser = Parser {
+            chars: xpath.chars().collect(),
+            at: 0,
+            namespaces,
+        };
+        let mut steps = Vec::new();
+        loop {
+            parser.skip_whitespace();
+            if parser.at_end() && !steps.is_empty() {
+                return Ok(XPath(steps));
+            }
+            let descendant = if parser.accept("//") {
+                true
+            } else if parser.accept("/") {
+                false
+            } else {
+                return Err(parser.unexpected("/ or //"));
+            };
+            let step = parser.step(descendant)?;
+            let last = !matches!(step.kind, StepKind::Elements(..));
+            steps.push(step);
+            parser.skip_whitespace();
+            if last && !parser.at_end() {
+                return Err("@attribute and text() can only be the last step".to_string());
+            }
+        }
+    }
+
+    // the string values of the nodes selected, in document order
+    pub fn evaluate(&self, document: &Document) -> Vec<String> {
+        let mut nodes = vec![document.root()];
+        for step in &self.0 {
+            let parents = match step.descendant {
+                true => descendants_or_self(&nodes),
+                false => nodes,
+            };
+            match &step.kind {
+                StepKind::Elements(test, predicates) => {
+                    nodes = Vec::new();
+                    for parent in parents {
+                        let mut children: Vec<Node> = parent
+                            .children()
+                            .filter(|child| child.is_element() && test.matches(child.tag_name()))
+                            .collect();
+                        for predicate in predicates {
+                            children = predicate.filter(children);
+                        }
+                        nodes.extend(children);
+                    }
+                    nodes.sort_by_key(|node| node.id().get_usize());
+                }
+                StepKind::Attribute(test) => {
+                    return parents
+                        .iter()
+                        .flat_map(Node::attributes)
+                        .filter(|attribute| match test {
+                            NameTest::Any => true,
+                            NameTest::Name((namespace, local_name)) => {
+                                attribute.namespace() == namespace.as_deref() && attribute.name() == local_name
+                            }
+                        })
+                        .map(|attribute| attribute.value().to_string())
+                        .collect();
+                }
+                StepKind::Text => {
+                    return parents
+                        .iter()
+                        .flat_map(Node::children)
+                        .filter(Node::is_text)
+                        .map(|child| child.text().unwrap_or_default().to_string())
+                        .collect();
+                }
+            }
+        }
+        nodes.iter().map(string_value).collect()
+    }
+}
+
+// the nodes and all their descendants, each once, in document order (given nodes in document order)
+fn descendants_or_self<'a, 'input>(nodes: &[Node<'a, 'input>]) -> Vec<Node<'a, 'input>> {
+    let mut seen = HashSet::new();
+    let mut result = Vec::new();
+    for node in nodes {
+        // if this node has been seen, it's inside an earlier one, whose descendants include all of its own
+        if seen.contains(&node.id()) {
+            continue;
+        }
+        for descendant in node.descendants() {
+            if seen.insert(descendant.id()) {
+                result.push(descendant);
+            }
+        }
+    }
+    result
+}
+
+// an element's text, including that of its descendants
+fn string_value(node: &Node) -> String {
+    node.descendants()
+        .filter(Node::is_text)
+        .filter_map(|node| node.text())
+        .collect()
+}
+
+// === Tests ===
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::*;
+    use pgrx::prelude::*;
+
+    const LIBRARY: &str = r#"<library>
+          <book id="b1" year="1843"><title>Notes</title><author>Ada</author></book>
+          <book id="b2"><title>Passages</title><author>Charles</author><author>Ada</author></book>
+          <shelf xmlns:s="http://example.com/shelf">
+            <s:label s:colour="red">Rare</s:label>
+            <book id="b3"><title>Sketch <i>of</i> the engine</title></book>
+          </shelf>
+        </library>"#;
+
+    fn evaluate(xpath: &str) -> Result<Vec<String>, String> {
+        let document = Document::parse(LIBRARY).unwrap();
+        Ok(XPath::parse(xpath, &namespaces(&document))?.evaluate(&document))
+    }
+
+    #[pg_test]
+    fn test_xpath() {
+        let cases: [(&str, &[&str]); 15] = [
+            ("/library/book/title", &["Notes", "Passages"]),
+            ("//book/title", &["Notes", "Passages", "Sketch of the engine"]),
+            ("//title/text()", &["Notes", "Passages", "Sketch ", " the engine"]),
+            ("/library//i", &["of"]),
+            ("//book[2]/@id", &["b2"]),
+            // positions count among each parent's children, so this is the first book on each shelf
+            ("//book[1]/@id", &["b1", "b3"]),
+            ("//book[@year]/title", &["Notes"]),
+            ("//book[@id='b2']/author[2]", &["Ada"]),
+            ("//book[@id = \"b2\"][1]/author[1]", &["Charles"]),
+            ("//book[@id][3]", &[]),
+            ("/library/*/@id", &["b1", "b2"]),
+            ("//s:label", &["Rare"]),
+            ("//s:label/@s:colour", &["red"]),
+            ("//label", &[]),
+            ("//book//author", &["Ada", "Charles", "Ada"]),
+        ];
+        for (xpath, expected) in cases {
+            assert_eq!(
+                evaluate(xpath),
+                Ok(expected.iter().map(|s| s.to_string()).collect()),
+                "{xpath}"
+            );
+        }
+    }
+
+    #[pg_test]
+    fn test_xpath_errors() {
+        let cases = [
+            (
+                "//book/",
+                "unexpected end of XPath: expected an element name, *, @attribute or text()",
+            ),
+            ("", "unexpected end of XPath: expected / or //"),
+            ("book", "expected / or // at character 1"),
+            ("count(//book)", "expected / or // at character 1"),
+            (
+                "//book/..",
+                "expected an element name, *, @attribute or text() at character 8",
+            ),
+            ("//p:book", "undeclared namespace prefix p"),
+            ("//book/@id/title", "@attribute and text() can only be the last step"),
+            (
+                "//book[last()]",
+                "expected a position, @name or @name='value' at character 8",
+            ),
+            ("//book[@id='b1'", "unexpected end of XPath: expected ]"),
+            ("//book[@id='b1]", "unterminated quoted value"),
+        ];
+        for (xpath, expected) in cases {
+            assert_eq!(evaluate(xpath).err().as_deref(), Some(expected), "{xpath}");
+        }
+    }
+}